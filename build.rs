@@ -1,10 +1,41 @@
 extern crate static_files;
 
-use static_files::load_files;
+use static_files::{load_files_with_config, Config};
 use static_files::file::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Files and directories the assets are built from. The sass include paths are here
+/// because stylesheets import from them.
+const INPUTS: &'static [&'static str] = &["static",
+                                          "bower.json",
+                                          "bower_components/foundation-sites/scss",
+                                          "bower_components/font-awesome/scss",
+                                          "bower_components/font-awesome/fonts"];
+
+/// Ask cargo to re-run this script when anything under `path` changes. Directories are
+/// listed along with their contents, so adding a file counts too.
+fn rerun_if_changed(path: &Path) {
+    println!("cargo:rerun-if-changed={}", path.display());
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries {
+            rerun_if_changed(&entry.expect("Couldn't read directory entry").path());
+        }
+    }
+}
 
 fn main() {
     #![allow(unused_mut)]
+    // Once any of these are printed cargo stops re-running the script on every change
+    // to the package, so everything it reads has to be listed.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=STATIC_CDN");
+    println!("cargo:rerun-if-env-changed=SASS_VERSION");
+    for input in INPUTS {
+        rerun_if_changed(Path::new(input));
+    }
+
     let mut args = vec!["--scss",
                        "-Istatic/css",
                        "-Ibower_components/foundation-sites/scss",
@@ -13,7 +44,20 @@ fn main() {
     #[cfg(not(debug_assertions))]
     args.append(&mut vec!["--style", "compact"]);
 
-    load_files(vec![plain("favicon.ico", "static/img/favicon.ico"),
+    let mut config = Config::new()
+        .prefix("/s")
+        .budget("css/all.css", 60 * 1024)
+        .total_budget(1024 * 1024)
+        .compressed_budgets()
+        .dev_dir("", "static", args.clone());
+    if let Ok(cdn) = env::var("STATIC_CDN") {
+        config = config.cdn(cdn);
+    }
+    if let Ok(version) = env::var("SASS_VERSION") {
+        config = config.sass_version(version);
+    }
+
+    load_files_with_config(vec![plain("favicon.ico", "static/img/favicon.ico"),
                     plain("img/github.png", "static/img/github.png"),
                     plain("img/github@2x.png", "static/img/github@2x.png"),
                     plain("img/linkedin.png", "static/img/linkedin.png"),
@@ -22,9 +66,13 @@ fn main() {
                     plain("img/otter@2x.png", "static/img/otter@2x.png"),
                     plain("img/newpost.png", "static/img/newpost.png"),
                     plain("img/newpost@2x.png", "static/img/newpost@2x.png"),
+                    plain("js/preview.js", "static/js/preview.js"),
                     plain("fonts/fontawesome-webfont.woff2", "bower_components/font-awesome/fonts/fontawesome-webfont.woff2"),
                     plain("fonts/fontawesome-webfont.woff", "bower_components/font-awesome/fonts/fontawesome-webfont.woff"),
                     plain("fonts/fontawesome-webfont.ttf", "bower_components/font-awesome/fonts/fontawesome-webfont.ttf"),
+                    plain("fonts/fontawesome-webfont.eot", "bower_components/font-awesome/fonts/fontawesome-webfont.eot"),
+                    plain("fonts/fontawesome-webfont.svg", "bower_components/font-awesome/fonts/fontawesome-webfont.svg"),
                     sass_args("css/all.css", "static/css/all.scss", args)
-                    ])
+                    ],
+                   config)
 }
@@ -26,4 +26,5 @@ in (makeRustPlatform rustNightlyBin).buildRustPackage rec {
 
   KEY_FILE = keyFile;
   PGUSER = pgUser;
+  SASS_VERSION = (builtins.parseDrvName sass.name).version;
 }
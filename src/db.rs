@@ -1,25 +1,588 @@
 use chrono;
+use chrono::TimeZone;
 use postgres;
 use r2d2;
+use highlighting::plain_text;
 use r2d2_postgres::PostgresConnectionManager;
 
 pub type Pool = r2d2::Pool<PostgresConnectionManager>;
 
+/// Schema changes on top of the original `essay` table, applied at startup.
+const MIGRATIONS: &'static str = "
+    CREATE TABLE IF NOT EXISTS essay_redirect (
+        slug text PRIMARY KEY,
+        essay_id integer NOT NULL REFERENCES essay (id) ON DELETE CASCADE
+    );
+
+    ALTER TABLE essay ADD COLUMN IF NOT EXISTS deleted_at timestamp with time zone;
+
+    ALTER TABLE essay ADD COLUMN IF NOT EXISTS status text NOT NULL DEFAULT 'published';
+    ALTER TABLE essay ADD COLUMN IF NOT EXISTS published_at timestamp with time zone;
+    UPDATE essay SET published_at = created_at
+        WHERE status <> 'draft' AND published_at IS NULL;
+    ALTER TABLE essay ALTER COLUMN published_at SET DEFAULT now();
+
+    CREATE TABLE IF NOT EXISTS essay_revision (
+        id serial PRIMARY KEY,
+        essay_id integer NOT NULL REFERENCES essay (id) ON DELETE CASCADE,
+        author text,
+        title text NOT NULL,
+        content text NOT NULL,
+        created_at timestamp with time zone NOT NULL
+    );
+    INSERT INTO essay_revision (essay_id, title, content, created_at)
+        SELECT id, title, content, created_at FROM essay e
+        WHERE NOT EXISTS (SELECT 1 FROM essay_revision r WHERE r.essay_id = e.id);
+
+    CREATE TABLE IF NOT EXISTS tag (
+        id serial PRIMARY KEY,
+        name text NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS essay_tag (
+        essay_id integer NOT NULL REFERENCES essay (id) ON DELETE CASCADE,
+        tag_id integer NOT NULL REFERENCES tag (id) ON DELETE CASCADE,
+        PRIMARY KEY (essay_id, tag_id)
+    );
+
+    ALTER TABLE essay ADD COLUMN IF NOT EXISTS search tsvector;
+    CREATE OR REPLACE FUNCTION essay_search_update() RETURNS trigger AS $$
+    BEGIN
+        NEW.search := setweight(to_tsvector('english', NEW.title), 'A') ||
+                      setweight(to_tsvector('english', NEW.content), 'B');
+        RETURN NEW;
+    END
+    $$ LANGUAGE plpgsql;
+    DROP TRIGGER IF EXISTS essay_search_update ON essay;
+    CREATE TRIGGER essay_search_update BEFORE INSERT OR UPDATE OF title, content ON essay
+        FOR EACH ROW EXECUTE PROCEDURE essay_search_update();
+    UPDATE essay SET title = title WHERE search IS NULL;
+    CREATE INDEX IF NOT EXISTS essay_search_index ON essay USING gin (search);
+
+    ALTER TABLE essay ADD COLUMN IF NOT EXISTS updated_at timestamp with time zone;
+    UPDATE essay e SET updated_at = COALESCE(
+        (SELECT max(created_at) FROM essay_revision r WHERE r.essay_id = e.id), e.created_at)
+        WHERE updated_at IS NULL;
+    ALTER TABLE essay ALTER COLUMN updated_at SET DEFAULT now(),
+        ALTER COLUMN updated_at SET NOT NULL;
+";
+
+/// The condition for an essay to be shown to visitors who aren't logged in.
+pub const PUBLIC: &'static str = "status <> 'draft' AND published_at <= now()";
+
+/// The condition for an essay to be shown at all: it isn't deleted, and it's public
+/// unless the boolean query parameter `unpublished`, such as `$2`, is true. Queries take
+/// that as an `unpublished` argument, set for logged-in users, who also see drafts and
+/// essays scheduled for later.
+pub fn visible(unpublished: &str) -> String {
+    format!("deleted_at IS NULL AND ({} OR {})", PUBLIC, unpublished)
+}
+
+/// Selects essays with their tags, for `Entry::from_row`. Add conditions on `essay`'s
+/// columns after it.
+pub const SELECT_ENTRY: &'static str = "SELECT essay.id, essay.title, essay.slug, \
+                                        essay.content, essay.created_at, essay.status, \
+                                        essay.published_at, essay.updated_at, \
+                                        ARRAY(SELECT tag.name FROM essay_tag \
+                                        JOIN tag ON tag.id = essay_tag.tag_id \
+                                        WHERE essay_tag.essay_id = essay.id \
+                                        ORDER BY tag.name) AS tags FROM essay";
+
+pub fn migrate(conn: &postgres::Connection) {
+    conn.batch_execute(MIGRATIONS).unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Draft,
+    Published,
+    /// Published, but not shown until `published_at`.
+    Scheduled,
+}
+
+impl Status {
+    pub fn from_str(s: &str) -> Option<Status> {
+        match s {
+            "draft" => Some(Status::Draft),
+            "published" => Some(Status::Published),
+            "scheduled" => Some(Status::Scheduled),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Draft => "draft",
+            Status::Published => "published",
+            Status::Scheduled => "scheduled",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Entry {
+    pub id: i32,
     pub title: String,
     pub slug: String,
     pub content: String,
     pub created_at: chrono::DateTime<chrono::UTC>,
+    pub status: Status,
+    pub published_at: Option<chrono::DateTime<chrono::UTC>>,
+    pub tags: Vec<String>,
+    /// When the essay was last saved, i.e. when its latest revision was made.
+    pub updated_at: chrono::DateTime<chrono::UTC>,
 }
 
 impl Entry {
     pub fn from_row<'a>(r: postgres::rows::Row<'a>) -> Entry {
+        let status: String = r.get("status");
         Entry {
-            title: r.get(1),
-            slug: r.get(2),
-            content: r.get(3),
-            created_at: r.get(4),
+            id: r.get("id"),
+            title: r.get("title"),
+            slug: r.get("slug"),
+            content: r.get("content"),
+            created_at: r.get("created_at"),
+            status: Status::from_str(&status).unwrap_or(Status::Draft),
+            published_at: r.get("published_at"),
+            tags: r.get("tags"),
+            updated_at: r.get("updated_at"),
+        }
+    }
+
+    /// Whether visitors who aren't logged in can see this essay.
+    pub fn is_public(&self) -> bool {
+        match (self.status, self.published_at) {
+            (Status::Draft, _) | (_, None) => false,
+            (_, Some(t)) => t <= chrono::UTC::now(),
+        }
+    }
+}
+
+/// A saved version of an essay.
+#[derive(Debug)]
+pub struct Revision {
+    pub id: i32,
+    /// Who saved it. Essays written before revisions were kept start with one that has
+    /// no author.
+    pub author: Option<String>,
+    pub title: String,
+    pub content: String,
+    pub created_at: chrono::DateTime<chrono::UTC>,
+}
+
+impl Revision {
+    pub fn from_row<'a>(r: postgres::rows::Row<'a>) -> Revision {
+        Revision {
+            id: r.get("id"),
+            author: r.get("author"),
+            title: r.get("title"),
+            content: r.get("content"),
+            created_at: r.get("created_at"),
+        }
+    }
+}
+
+/// The editable parts of an essay.
+pub struct Fields<'a> {
+    pub title: &'a str,
+    pub content: &'a str,
+    pub status: Status,
+    pub published_at: Option<chrono::DateTime<chrono::UTC>>,
+    pub tags: Vec<String>,
+}
+
+/// Lowercase ASCII letters and digits from `s`, with each run of anything else turned
+/// into a single dash. Empty if `s` has no letters or digits.
+fn dashed_words(s: &str) -> String {
+    let mut words = String::new();
+    for c in s.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_digit(36) {
+            words.push(c);
+        } else if !words.is_empty() && !words.ends_with('-') {
+            words.push('-');
+        }
+    }
+    words.trim_right_matches('-').to_string()
+}
+
+/// Turn a title into a URL-friendly slug: lowercase ASCII letters and digits separated
+/// by single dashes.
+pub fn slugify(title: &str) -> String {
+    let slug = dashed_words(title);
+    if slug.is_empty() {
+        String::from("post")
+    } else {
+        slug
+    }
+}
+
+/// Split a comma-separated list of tags into the same form as slugs, dropping repeats
+/// and tags with no letters or digits.
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    let mut parsed: Vec<String> = Vec::new();
+    for tag in tags.split(',') {
+        let tag = dashed_words(tag);
+        if !tag.is_empty() && !parsed.contains(&tag) {
+            parsed.push(tag);
+        }
+    }
+    parsed
+}
+
+/// A slug for `title` that no essay uses yet, made unique with a numeric suffix.
+pub fn unique_slug(conn: &postgres::Connection, title: &str) -> String {
+    let base = slugify(title);
+    let mut slug = base.clone();
+    let mut n = 2;
+    while slug_taken(conn, &slug, None) {
+        slug = format!("{}-{}", base, n);
+        n += 1;
+    }
+    slug
+}
+
+/// Insert a new essay written by `author`, returning its slug.
+pub fn insert_entry(conn: &postgres::Connection, fields: &Fields, author: &str) -> String {
+    let slug = unique_slug(conn, fields.title);
+    let tx = conn.transaction().unwrap();
+    let rows = tx.query("INSERT INTO essay (title, slug, content, created_at, status, \
+                         published_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                        &[&fields.title,
+                          &slug,
+                          &fields.content,
+                          &chrono::UTC::now(),
+                          &fields.status.as_str(),
+                          &fields.published_at])
+        .unwrap();
+    let id = rows.get(0).get(0);
+    set_tags(&tx, id, &fields.tags);
+    add_revision(&tx, id, fields, author);
+    tx.commit().unwrap();
+    slug
+}
+
+fn set_tags(conn: &postgres::GenericConnection, essay_id: i32, tags: &[String]) {
+    conn.execute("DELETE FROM essay_tag WHERE essay_id = $1", &[&essay_id]).unwrap();
+    for tag in tags {
+        conn.execute("INSERT INTO tag (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+                     &[tag])
+            .unwrap();
+        conn.execute("INSERT INTO essay_tag (essay_id, tag_id) SELECT $1, id FROM tag \
+                      WHERE name = $2",
+                     &[&essay_id, tag])
+            .unwrap();
+    }
+}
+
+/// Where a page of the home page starts.
+#[derive(Clone, Copy, Debug)]
+pub enum Cursor {
+    Latest,
+    /// Essays that come before this one, i.e. older ones.
+    Before(Key),
+    /// Essays that come after this one.
+    After(Key),
+}
+
+/// An essay's place in the order of the home page. Essays written at the same time are
+/// ordered by id, so every essay has its own place.
+#[derive(Clone, Copy, Debug)]
+pub struct Key {
+    pub created_at: chrono::DateTime<chrono::UTC>,
+    pub id: i32,
+}
+
+/// How the time in a key appears in URLs.
+const KEY_TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+impl Key {
+    pub fn of(entry: &Entry) -> Key {
+        Key {
+            created_at: entry.created_at,
+            id: entry.id,
         }
     }
+
+    /// Read a key as written by `to_param`.
+    pub fn from_param(param: &str) -> Option<Key> {
+        let mut parts = param.splitn(2, ',');
+        let time = parts.next().and_then(|t| {
+            chrono::UTC.datetime_from_str(t, KEY_TIME_FORMAT).ok()
+        });
+        let id = parts.next().and_then(|i| i.parse().ok());
+        match (time, id) {
+            (Some(t), Some(i)) => Some(Key { created_at: t, id: i }),
+            _ => None,
+        }
+    }
+
+    /// The key as a query parameter value, `<created_at>,<id>`.
+    pub fn to_param(&self) -> String {
+        format!("{},{}", self.created_at.format(KEY_TIME_FORMAT), self.id)
+    }
+}
+
+/// Up to `size` essays from `cursor`, newest first, and whether there are more past
+/// them in the direction the cursor goes.
+pub fn entry_page(conn: &postgres::Connection,
+                  cursor: Cursor,
+                  size: i64,
+                  unpublished: bool)
+                  -> (Vec<Entry>, bool) {
+    let (condition, order, key) = match cursor {
+        Cursor::Latest => ("", "DESC", None),
+        Cursor::Before(k) => ("AND (created_at, id) < ($3, $4)", "DESC", Some(k)),
+        Cursor::After(k) => ("AND (created_at, id) > ($3, $4)", "ASC", Some(k)),
+    };
+    let query = format!("{0} WHERE {1} {2} ORDER BY created_at {3}, id {3} LIMIT $2",
+                        SELECT_ENTRY,
+                        visible("$1"),
+                        condition,
+                        order);
+    // One more than fits, to tell whether there's another page.
+    let limit = size + 1;
+    let rows = match key {
+        Some(k) => conn.query(query.as_str(), &[&unpublished, &limit, &k.created_at, &k.id]),
+        None => conn.query(query.as_str(), &[&unpublished, &limit]),
+    };
+    let mut entries: Vec<Entry> = rows.unwrap().iter().map(|r| Entry::from_row(r)).collect();
+    let more = entries.len() as i64 > size;
+    entries.truncate(size as usize);
+    if let Cursor::After(_) = cursor {
+        entries.reverse();
+    }
+    (entries, more)
+}
+
+/// The `limit` most recently published essays that visitors can see, for feeds. They're
+/// ordered by when they went up rather than when they were written, so drafts and
+/// scheduled essays show up once they're published.
+pub fn feed_entries(conn: &postgres::Connection, limit: i64) -> Vec<Entry> {
+    conn.query(format!("{} WHERE {} ORDER BY published_at DESC, id DESC LIMIT $1",
+                       SELECT_ENTRY,
+                       visible("false"))
+                   .as_str(),
+               &[&limit])
+        .unwrap()
+        .iter()
+        .map(|r| Entry::from_row(r))
+        .collect()
+}
+
+/// Where `search` puts the start and end of each match in a snippet. They're from the
+/// private use area, so they won't turn up in essays.
+pub const MATCH_START: char = '\u{e000}';
+pub const MATCH_END: char = '\u{e001}';
+
+/// Up to `limit` essays matching `query`, best first, each with snippets of its text
+/// around the matches.
+pub fn search(conn: &postgres::Connection,
+              query: &str,
+              limit: i64,
+              unpublished: bool)
+              -> Vec<(Entry, String)> {
+    let entries: Vec<Entry> = conn.query(format!("{} WHERE {} \
+                                                  AND search @@ plainto_tsquery('english', $1) \
+                                                  ORDER BY ts_rank(search, \
+                                                  plainto_tsquery('english', $1)) DESC, \
+                                                  created_at DESC LIMIT $3",
+                                                 SELECT_ENTRY,
+                                                 visible("$2"))
+                                             .as_str(),
+                                         &[&query, &unpublished, &limit])
+        .unwrap()
+        .iter()
+        .map(|r| Entry::from_row(r))
+        .collect();
+    if entries.is_empty() {
+        return vec![];
+    }
+
+    // Snippets come from the text without its Markdown, so they don't show syntax.
+    let texts: Vec<String> = entries.iter().map(|e| plain_text(&e.content)).collect();
+    let options = format!("StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10",
+                          MATCH_START,
+                          MATCH_END);
+    let snippets: Vec<String> = conn.query("SELECT ts_headline('english', doc, \
+                                            plainto_tsquery('english', $1), $3) \
+                                            FROM unnest($2::text[]) WITH ORDINALITY \
+                                            AS d(doc, n) ORDER BY n",
+                                           &[&query, &texts, &options])
+        .unwrap()
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+    entries.into_iter().zip(snippets).collect()
+}
+
+/// Essays tagged `tag`, newest first.
+pub fn tagged_entries(conn: &postgres::Connection, tag: &str, unpublished: bool) -> Vec<Entry> {
+    conn.query(format!("{} WHERE {} AND EXISTS \
+                        (SELECT 1 FROM essay_tag JOIN tag ON tag.id = essay_tag.tag_id \
+                        WHERE essay_tag.essay_id = essay.id AND tag.name = $1) \
+                        ORDER BY created_at DESC",
+                       SELECT_ENTRY,
+                       visible("$2"))
+                   .as_str(),
+               &[&tag, &unpublished])
+        .unwrap()
+        .iter()
+        .map(|r| Entry::from_row(r))
+        .collect()
+}
+
+/// Record `fields` as the latest revision, and the time as the essay's `updated_at`.
+fn add_revision(conn: &postgres::GenericConnection, essay_id: i32, fields: &Fields, author: &str) {
+    let now = chrono::UTC::now();
+    conn.execute("INSERT INTO essay_revision (essay_id, author, title, content, created_at) \
+                  VALUES ($1, $2, $3, $4, $5)",
+                 &[&essay_id, &author, &fields.title, &fields.content, &now])
+        .unwrap();
+    conn.execute("UPDATE essay SET updated_at = $1 WHERE id = $2", &[&now, &essay_id]).unwrap();
+}
+
+/// Every saved version of an essay, newest first.
+pub fn revisions(conn: &postgres::Connection, entry: &Entry) -> Vec<Revision> {
+    conn.query("SELECT * FROM essay_revision WHERE essay_id = $1 \
+                ORDER BY created_at DESC, id DESC",
+               &[&entry.id])
+        .unwrap()
+        .iter()
+        .map(|r| Revision::from_row(r))
+        .collect()
+}
+
+pub fn find_revision(conn: &postgres::Connection, entry: &Entry, id: i32) -> Option<Revision> {
+    let rows = conn.query("SELECT * FROM essay_revision WHERE essay_id = $1 AND id = $2",
+                          &[&entry.id, &id])
+        .unwrap();
+    rows.iter().next().map(|r| Revision::from_row(r))
+}
+
+/// The essay at `slug`.
+pub fn find_entry(conn: &postgres::Connection, slug: &str, unpublished: bool) -> Option<Entry> {
+    let rows = conn.query(format!("{} WHERE slug = $1 AND {}", SELECT_ENTRY, visible("$2"))
+                              .as_str(),
+                          &[&slug, &unpublished])
+        .unwrap();
+    match rows.len() {
+        1 => Some(Entry::from_row(rows.get(0))),
+        _ => None,
+    }
+}
+
+/// The current slug of the essay that used to be at `slug`, if it moved.
+pub fn find_redirect(conn: &postgres::Connection, slug: &str, unpublished: bool) -> Option<String> {
+    let rows = conn.query(format!("SELECT e.slug FROM essay_redirect r \
+                                   JOIN essay e ON e.id = r.essay_id \
+                                   WHERE r.slug = $1 AND {}",
+                                  visible("$2"))
+                              .as_str(),
+                          &[&slug, &unpublished])
+        .unwrap();
+    rows.iter().next().map(|r| r.get(0))
+}
+
+/// Whether `slug` belongs to, or redirects to, an essay other than `except`.
+pub fn slug_taken(conn: &postgres::Connection, slug: &str, except: Option<i32>) -> bool {
+    !conn.query("SELECT 1 FROM essay WHERE slug = $1 AND id IS DISTINCT FROM $2 \
+                 UNION ALL SELECT 1 FROM essay_redirect \
+                 WHERE slug = $1 AND essay_id IS DISTINCT FROM $2",
+                &[&slug, &except])
+        .unwrap()
+        .is_empty()
+}
+
+/// Save changes to an essay made by `author`, keeping the new version as a revision. If
+/// its slug changed, the old one redirects to it.
+pub fn update_entry(conn: &postgres::Connection,
+                    entry: &Entry,
+                    slug: &str,
+                    fields: &Fields,
+                    author: &str) {
+    let tx = conn.transaction().unwrap();
+    tx.execute("UPDATE essay SET title = $1, slug = $2, content = $3, status = $4, \
+                published_at = $5 WHERE id = $6",
+               &[&fields.title,
+                 &slug,
+                 &fields.content,
+                 &fields.status.as_str(),
+                 &fields.published_at,
+                 &entry.id])
+        .unwrap();
+    if slug != entry.slug {
+        tx.execute("DELETE FROM essay_redirect WHERE slug = $1", &[&slug]).unwrap();
+        tx.execute("INSERT INTO essay_redirect (slug, essay_id) VALUES ($1, $2) \
+                    ON CONFLICT (slug) DO UPDATE SET essay_id = EXCLUDED.essay_id",
+                   &[&entry.slug, &entry.id])
+            .unwrap();
+    }
+    set_tags(&tx, entry.id, &fields.tags);
+    add_revision(&tx, entry.id, fields, author);
+    tx.commit().unwrap();
+}
+
+/// Hide an essay from every page. Deleted essays keep their slugs, and come back by
+/// clearing `deleted_at`. Returns whether there was an essay to delete.
+pub fn delete_entry(conn: &postgres::Connection, slug: &str) -> bool {
+    conn.execute("UPDATE essay SET deleted_at = $1 WHERE slug = $2 AND deleted_at IS NULL",
+                 &[&chrono::UTC::now(), &slug])
+        .unwrap() > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, parse_tags, slugify};
+    use chrono;
+    use chrono::TimeZone;
+
+    #[test]
+    fn slugify_joins_words_with_dashes() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust 1.15 -- released  "), "rust-1-15-released");
+    }
+
+    #[test]
+    fn slugify_falls_back_to_post() {
+        assert_eq!(slugify("!!!"), "post");
+        assert_eq!(slugify(""), "post");
+    }
+
+    #[test]
+    fn slugify_turns_non_ascii_into_dashes() {
+        assert_eq!(slugify("Café au lait"), "caf-au-lait");
+        assert_eq!(slugify("日本語"), "post");
+    }
+
+    #[test]
+    fn parse_tags_dedupes_and_drops_empty_tags() {
+        assert_eq!(parse_tags(" Rust, rust ,!!,a b"), vec!["rust", "a-b"]);
+        assert_eq!(parse_tags(""), Vec::<String>::new());
+        assert_eq!(parse_tags("naïve, 日本語"), vec!["na-ve"]);
+    }
+
+    fn key() -> Key {
+        Key {
+            created_at: chrono::UTC.ymd(2017, 3, 14).and_hms_micro(15, 9, 26, 535897),
+            id: 42,
+        }
+    }
+
+    #[test]
+    fn key_params_round_trip() {
+        let k = key();
+        assert_eq!(k.to_param(), "2017-03-14T15:09:26.535897Z,42");
+        let parsed = Key::from_param(&k.to_param()).unwrap();
+        assert_eq!(parsed.created_at, k.created_at);
+        assert_eq!(parsed.id, k.id);
+    }
+
+    #[test]
+    fn malformed_key_params_are_rejected() {
+        assert!(Key::from_param("x").is_none());
+        assert!(Key::from_param("").is_none());
+        assert!(Key::from_param("2017-03-14T15:09:26.535897Z").is_none());
+        assert!(Key::from_param("2017-03-14T15:09:26.535897Z,").is_none());
+        assert!(Key::from_param("2017-03-14T15:09:26.535897Z,42,7").is_none());
+        assert!(Key::from_param(",42").is_none());
+    }
 }
@@ -0,0 +1,215 @@
+use chrono;
+use chrono::TimeZone;
+use db::Entry;
+use highlighting::markdown;
+use maud::PreEscaped;
+use rocket::Outcome;
+use rocket::http::ContentType;
+use rocket::http::Status;
+use rocket::request::FromRequest;
+use rocket::request::Request;
+use rocket::response::Responder;
+use rocket::response::Response;
+use static_files::file;
+use std::io::Cursor;
+
+/// How many of the latest essays the feeds include.
+pub const LENGTH: i64 = 20;
+
+const TITLE: &'static str = "jude.bio";
+const AUTHOR: &'static str = "Jude Taylor";
+const EMAIL: &'static str = "me@jude.bio";
+
+/// A rendered feed, sent with the headers feed readers use to poll cheaply.
+pub struct Feed {
+    content_type: ContentType,
+    body: String,
+    etag: String,
+    last_modified: chrono::DateTime<chrono::UTC>,
+    /// Send `304 Not Modified` instead of the body.
+    not_modified: bool,
+}
+
+impl Feed {
+    fn new(content_type: ContentType,
+           body: String,
+           last_modified: chrono::DateTime<chrono::UTC>)
+           -> Feed {
+        Feed {
+            content_type: content_type,
+            etag: file::etag(&body),
+            body: body,
+            last_modified: last_modified,
+            not_modified: false,
+        }
+    }
+
+    /// Answer with `304 Not Modified` if the client's copy, described by `v`, is
+    /// current.
+    pub fn check(mut self, v: &Validators) -> Feed {
+        self.not_modified = self.fresh(v);
+        self
+    }
+
+    /// Whether the copy described by `v` is still current. An etag, if the client sent
+    /// one, takes precedence over a date.
+    fn fresh(&self, v: &Validators) -> bool {
+        match (&v.if_none_match, &v.if_modified_since) {
+            (&Some(ref inm), _) => inm.split(',').any(|t| t.trim() == self.etag),
+            (&None, &Some(ref ims)) => {
+                match chrono::DateTime::parse_from_rfc2822(ims) {
+                    Ok(t) => self.last_modified.timestamp() <= t.timestamp(),
+                    Err(_) => false,
+                }
+            }
+            (&None, &None) => false,
+        }
+    }
+}
+
+impl<'r> Responder<'r> for Feed {
+    fn respond(self) -> Result<Response<'r>, Status> {
+        let mut response = Response::build();
+        response.raw_header("Etag", self.etag)
+            .raw_header("Last-Modified",
+                        self.last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+        if self.not_modified {
+            response.status(Status::NotModified).ok()
+        } else {
+            response.header(self.content_type)
+                .sized_body(Cursor::new(self.body))
+                .ok()
+        }
+    }
+}
+
+/// The conditional request headers feed readers send.
+pub struct Validators {
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Validators {
+    type Error = ();
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, (Status, ()), ()> {
+        let headers = req.headers();
+        Outcome::Success(Validators {
+            if_none_match: headers.get_one("If-None-Match").map(String::from),
+            if_modified_since: headers.get_one("If-Modified-Since").map(String::from),
+        })
+    }
+}
+
+/// When an entry last changed, as far as a feed reader is concerned.
+fn updated(entry: &Entry) -> chrono::DateTime<chrono::UTC> {
+    match entry.published_at {
+        Some(p) if p > entry.updated_at => p,
+        _ => entry.updated_at,
+    }
+}
+
+fn published(entry: &Entry) -> chrono::DateTime<chrono::UTC> {
+    entry.published_at.unwrap_or(entry.created_at)
+}
+
+fn last_modified(entries: &[Entry]) -> chrono::DateTime<chrono::UTC> {
+    entries.iter().map(updated).max().unwrap_or_else(|| chrono::UTC.timestamp(0, 0))
+}
+
+/// An id for `entry` that survives changes to its slug.
+fn entry_id(site: &str, entry: &Entry) -> String {
+    let host = site.splitn(2, "://").last().unwrap_or(site);
+    format!("tag:{},{}:essay/{}", host, entry.created_at.format("%Y-%m-%d"), entry.id)
+}
+
+/// `entry`'s content as HTML, with its root-relative links and images, such as
+/// `/r/...` or `/s/img/...`, made absolute under `site`, since feed readers show it
+/// away from the site.
+fn content(site: &str, entry: &Entry) -> String {
+    let html = markdown(entry.content.clone());
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(i) = rest.find("=\"/") {
+        let (before, after) = rest.split_at(i + 2);
+        let attr = before[..i].rsplit(char::is_whitespace).next().unwrap_or("");
+        out.push_str(before);
+        if (attr == "href" || attr == "src") && !after.starts_with("//") {
+            out.push_str(site);
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+fn xml_declaration() -> PreEscaped<&'static str> {
+    PreEscaped("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n")
+}
+
+/// An Atom feed of `entries`, with links under `site`, e.g. `https://jude.bio`.
+pub fn atom(site: &str, entries: Vec<Entry>) -> Feed {
+    let last_modified = last_modified(&entries);
+    let body = html! {
+      (xml_declaration())
+      feed xmlns="http://www.w3.org/2005/Atom" {
+        title (TITLE)
+        link rel="alternate" type="text/html" href={ (site) "/" } {}
+        link rel="self" type="application/atom+xml" href={ (site) "/feed.atom" } {}
+        id { (site) "/" }
+        updated (last_modified.to_rfc3339())
+        author {
+          name (AUTHOR)
+          email (EMAIL)
+        }
+        @for entry in &entries {
+          entry {
+            title (entry.title)
+            link rel="alternate" type="text/html" href={ (site) "/r/" (entry.slug) } {}
+            id (entry_id(site, entry))
+            published (published(entry).to_rfc3339())
+            updated (updated(entry).to_rfc3339())
+            @for tag in &entry.tags {
+              category term=(tag) {}
+            }
+            content type="html" (content(site, entry))
+          }
+        }
+      }
+    };
+    Feed::new(ContentType::new("application", "atom+xml"),
+              body.into_string(),
+              last_modified)
+}
+
+/// An RSS 2.0 feed of `entries`, with links under `site`.
+pub fn rss(site: &str, entries: Vec<Entry>) -> Feed {
+    let last_modified = last_modified(&entries);
+    let body = html! {
+      (xml_declaration())
+      rss version="2.0" {
+        channel {
+          title (TITLE)
+          link { (site) "/" }
+          description { "Essays by " (AUTHOR) }
+          lastBuildDate (last_modified.to_rfc2822())
+          @for entry in &entries {
+            item {
+              title (entry.title)
+              link { (site) "/r/" (entry.slug) }
+              guid isPermaLink="false" (entry_id(site, entry))
+              pubDate (published(entry).to_rfc2822())
+              author { (EMAIL) " (" (AUTHOR) ")" }
+              @for tag in &entry.tags {
+                category (tag)
+              }
+              description (content(site, entry))
+            }
+          }
+        }
+      }
+    };
+    Feed::new(ContentType::new("application", "rss+xml"),
+              body.into_string(),
+              last_modified)
+}
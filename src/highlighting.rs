@@ -73,3 +73,20 @@ pub fn markdown(text: String) -> String {
     push_html(&mut html_str, prs);
     html_str
 }
+
+/// The text of some Markdown without its markup, one block to a line, for searching.
+pub fn plain_text(text: &str) -> String {
+    let mut plain = String::new();
+    for event in Parser::new(text) {
+        match event {
+            Event::Text(t) => plain.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => plain.push(' '),
+            Event::End(Tag::Paragraph) |
+            Event::End(Tag::Header(_)) |
+            Event::End(Tag::Item) |
+            Event::End(Tag::CodeBlock(_)) => plain.push('\n'),
+            _ => {}
+        }
+    }
+    plain
+}
@@ -7,6 +7,7 @@
 #![allow(non_snake_case)]
 
 extern crate chrono;
+extern crate diff;
 extern crate rand;
 extern crate static_files;
 extern crate maud;
@@ -20,11 +21,13 @@ extern crate sodiumoxide;
 extern crate state;
 extern crate syntect;
 
-use static_files::{lookup_file, StaticResponse, Cached, IfNoneMatch};
+use static_files::{lookup_file_guarded, LookupOptions, StaticResponse, Cached, IfNoneMatch};
 use r2d2_postgres::{PostgresConnectionManager, TlsMode};
+use chrono::TimeZone;
 use rocket::request::Form;
 use rocket::State;
 use rocket::response::content::HTML;
+use std::env;
 use std::path::PathBuf;
 
 mod pages;
@@ -32,50 +35,161 @@ mod db;
 mod highlighting;
 mod session;
 mod auth;
+mod feed;
 
 use db::Entry;
 use db::Pool;
+use pages::editor::{Draft, FieldError};
+use session::CurrentUser;
 use session::Session;
 
+mod_path! generated { concat!(env!("OUT_DIR"), "/generated.rs") }
+
+/// How many entries each page of the home page shows, from `PAGE_SIZE`.
+struct PageSize(i64);
+
+#[derive(FromForm)]
+struct HomeQuery { before: Option<String>, after: Option<String> }
+
+impl HomeQuery {
+    fn cursor(&self) -> Option<db::Cursor> {
+        match (self.before.as_ref(), self.after.as_ref()) {
+            (None, None) => Some(db::Cursor::Latest),
+            (Some(k), None) => db::Key::from_param(k).map(db::Cursor::Before),
+            (None, Some(k)) => db::Key::from_param(k).map(db::Cursor::After),
+            (Some(_), Some(_)) => None,
+        }
+    }
+}
+
 #[get("/")]
-fn home(db: State<Pool>, s: Session) -> HTML<String> {
+fn home(db: State<Pool>,
+        s: Session,
+        size: State<PageSize>)
+        -> Option<Result<HTML<String>, rocket::response::Redirect>> {
+    home_qs(db, s, size, HomeQuery { before: None, after: None })
+}
+
+#[get("/?<query>")]
+fn home_qs(db: State<Pool>,
+           s: Session,
+           size: State<PageSize>,
+           query: HomeQuery)
+           -> Option<Result<HTML<String>, rocket::response::Redirect>> {
+    let cursor = match query.cursor() {
+        Some(c) => c,
+        None => return None,
+    };
+
     let conn = db.get().unwrap();
     let user = s.user();
-    let entries: Vec<Entry> = conn.query("SELECT * FROM essay ORDER BY created_at DESC", &[])
-        .unwrap()
-        .iter()
-        .map(|r| Entry::from_row(r))
-        .collect();
+    let (entries, more) = db::entry_page(&conn, cursor, size.0, user.is_some());
+    let (newer, older) = match cursor {
+        db::Cursor::Latest => (false, more),
+        db::Cursor::Before(_) => (true, more),
+        // Paging back up to the newest entries lands on the first page proper, rather
+        // than a short page of whatever is left.
+        db::Cursor::After(_) if !more => return Some(Err(rocket::response::Redirect::to("/"))),
+        db::Cursor::After(_) => (true, true),
+    };
 
-    pages::home::page(user, entries)
+    let link = |key: &str, entry: Option<&Entry>| {
+        entry.map(|e| format!("/?{}={}", key, db::Key::of(e).to_param()))
+    };
+    let newer = if newer { link("after", entries.first()) } else { None };
+    let older = if older { link("before", entries.last()) } else { None };
+    Some(Ok(pages::home::page(user, entries, newer, older)))
 }
 
 #[get("/r/<slug>")]
-fn one(db: State<Pool>, slug: String, s: Session) -> Option<HTML<String>> {
+fn one(db: State<Pool>,
+       slug: String,
+       mut s: Session)
+       -> Option<Result<HTML<String>, rocket::response::Redirect>> {
     let conn = db.get().unwrap();
-    let rows = conn.query("SELECT * FROM essay WHERE slug = $1", &[&slug]).unwrap();
-    match rows.len() {
-        1 => Some(pages::one::page(s.user(), Entry::from_row(rows.get(0)))),
-        _ => None,
+    let user = s.user();
+    let logged_in = user.is_some();
+    match db::find_entry(&conn, &slug, logged_in) {
+        Some(entry) => {
+            let csrf = if logged_in { s.csrf_token() } else { String::new() };
+            Some(Ok(pages::one::page(user, &csrf, entry)))
+        }
+        None => {
+            db::find_redirect(&conn, &slug, logged_in)
+                .map(|to| Err(rocket::response::Redirect::permanent(format!("/r/{}", to).as_str())))
+        }
     }
 }
 
+#[get("/t/<tag>")]
+fn tagged(db: State<Pool>, tag: String, s: Session) -> HTML<String> {
+    let conn = db.get().unwrap();
+    let user = s.user();
+    let entries = db::tagged_entries(&conn, &tag, user.is_some());
+    pages::tag::page(user, &tag, entries)
+}
+
+/// How many results the search page shows.
+const SEARCH_RESULTS: i64 = 20;
+
+#[derive(FromForm)]
+struct SearchQuery { q: String }
+
+#[get("/search")]
+fn search(db: State<Pool>, s: Session) -> HTML<String> {
+    search_qs(db, s, SearchQuery { q: String::new() })
+}
+
+#[get("/search?<query>")]
+fn search_qs(db: State<Pool>, s: Session, query: SearchQuery) -> HTML<String> {
+    let user = s.user();
+    let results = if query.q.trim().is_empty() {
+        vec![]
+    } else {
+        let conn = db.get().unwrap();
+        db::search(&conn, &query.q, SEARCH_RESULTS, user.is_some())
+    };
+    pages::search::page(user, &query.q, results)
+}
+
+/// The scheme and host the site is served from, from `SITE_URL`, for links that leave
+/// it such as those in feeds.
+struct SiteUrl(String);
+
+#[get("/feed.atom")]
+fn atom_feed(db: State<Pool>, site: State<SiteUrl>, v: feed::Validators) -> feed::Feed {
+    let conn = db.get().unwrap();
+    let entries = db::feed_entries(&conn, feed::LENGTH);
+    feed::atom(&site.0, entries).check(&v)
+}
+
+#[get("/feed.rss")]
+fn rss_feed(db: State<Pool>, site: State<SiteUrl>, v: feed::Validators) -> feed::Feed {
+    let conn = db.get().unwrap();
+    let entries = db::feed_entries(&conn, feed::LENGTH);
+    feed::rss(&site.0, entries).check(&v)
+}
+
 #[get("/favicon.ico")]
-fn get_favicon(inm: Option<IfNoneMatch>) -> Option<Cached<StaticResponse>> {
-    get_static(PathBuf::from("favicon.ico"), inm)
+fn get_favicon(inm: Option<IfNoneMatch>, u: CurrentUser) -> Option<Cached<StaticResponse>> {
+    get_static(PathBuf::from("favicon.ico"), inm, u)
 }
 
-#[get("/s/<path..>")]
-fn get_static(path: PathBuf, inm: Option<IfNoneMatch>) -> Option<Cached<StaticResponse>> {
-  lookup_file(path, inm)
+#[get("/<path..>")]
+fn get_static(path: PathBuf,
+              inm: Option<IfNoneMatch>,
+              u: CurrentUser)
+              -> Option<Cached<StaticResponse>> {
+  lookup_file_guarded(generated::store(), path, inm, &LookupOptions::default(), &u)
 }
 
-#[get("/s/<path..>?<_query>")]
+#[get("/<path..>?<_query>")]
 fn static_qs(path: PathBuf,
              _query: &str,
-             inm: Option<IfNoneMatch>)
+             inm: Option<IfNoneMatch>,
+             u: CurrentUser)
              -> Option<Cached<StaticResponse>> {
-    get_static(path, inm)
+    get_static(path, inm, u)
 }
 
 #[get("/in")]
@@ -101,10 +215,375 @@ fn post_login(mut s: Session, password: Form<Password>) -> Result<HTML<String>,
     Ok(pages::login::page(Some((pw.username.clone(), "Invalid password"))))
 }
 
+/// The form token that every form which changes anything carries.
+#[derive(FromForm)]
+struct Token { csrf: String }
+
+/// Refuse a form submission unless it carries the session's form token, so other sites
+/// can't submit forms on a logged-in author's behalf.
+fn check_token(s: &Session, token: &str) -> Result<(), rocket::response::Failure> {
+    if s.check_csrf(token) {
+        Ok(())
+    } else {
+        Err(rocket::response::Failure(rocket::http::Status::Forbidden))
+    }
+}
+
+const PUBLISH_AT_FORMAT: &'static str = "%Y-%m-%d %H:%M";
+
+/// Check the fields shared by new and edited posts. A post that is already up keeps
+/// the time it was first published.
+fn validate_post<'a>(title: &'a str,
+                     content: &'a str,
+                     tags: &str,
+                     status: &str,
+                     publish_at: &str,
+                     previous: Option<&Entry>)
+                     -> Result<db::Fields<'a>, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    if title.trim().is_empty() {
+        errors.push(("title", "A title is required"));
+    }
+    if content.trim().is_empty() {
+        errors.push(("content", "Write something first"));
+    }
+
+    let now = chrono::UTC::now();
+    let publication = match db::Status::from_str(status) {
+        Some(db::Status::Draft) => Some((db::Status::Draft, None)),
+        Some(db::Status::Published) => {
+            let at = match previous.and_then(|e| e.published_at) {
+                Some(t) if t <= now => t,
+                _ => now,
+            };
+            Some((db::Status::Published, Some(at)))
+        }
+        Some(db::Status::Scheduled) => {
+            match chrono::UTC.datetime_from_str(publish_at.trim(), PUBLISH_AT_FORMAT) {
+                Ok(t) if t > now => Some((db::Status::Scheduled, Some(t))),
+                Ok(_) => {
+                    errors.push(("publish_at", "Scheduled posts need a time in the future"));
+                    None
+                }
+                Err(_) => {
+                    errors.push(("publish_at", "Use the format YYYY-MM-DD HH:MM"));
+                    None
+                }
+            }
+        }
+        None => {
+            errors.push(("status", "Pick a status"));
+            None
+        }
+    };
+
+    match publication {
+        Some((status, published_at)) if errors.is_empty() => {
+            Ok(db::Fields {
+                title: title.trim(),
+                content: content,
+                status: status,
+                published_at: published_at,
+                tags: db::parse_tags(tags),
+            })
+        }
+        _ => Err(errors),
+    }
+}
+
+#[derive(FromForm)]
+struct Post {
+    csrf: String,
+    title: String,
+    content: String,
+    tags: String,
+    status: String,
+    publish_at: String,
+}
+
+impl Post {
+    fn fields(&self) -> Result<db::Fields, Vec<FieldError>> {
+        validate_post(&self.title,
+                      &self.content,
+                      &self.tags,
+                      &self.status,
+                      &self.publish_at,
+                      None)
+    }
+
+    fn draft(&self) -> Draft {
+        Draft {
+            title: self.title.clone(),
+            content: self.content.clone(),
+            slug: None,
+            tags: self.tags.clone(),
+            status: self.status.clone(),
+            publish_at: self.publish_at.clone(),
+        }
+    }
+}
+
+#[derive(FromForm)]
+struct Edit {
+    csrf: String,
+    title: String,
+    slug: String,
+    content: String,
+    tags: String,
+    status: String,
+    publish_at: String,
+}
+
+impl Edit {
+    fn fields(&self,
+              conn: &postgres::Connection,
+              entry: &Entry)
+              -> Result<db::Fields, Vec<FieldError>> {
+        let fields = validate_post(&self.title,
+                                   &self.content,
+                                   &self.tags,
+                                   &self.status,
+                                   &self.publish_at,
+                                   Some(entry));
+        // Slugs from before this check existed may not be in slugify's form, so only
+        // new ones are held to it.
+        let slug_error = if self.slug == entry.slug {
+            None
+        } else if self.slug.is_empty() || db::slugify(&self.slug) != self.slug {
+            Some(("slug", "Slugs are lowercase letters and digits, separated by dashes"))
+        } else if db::slug_taken(conn, &self.slug, Some(entry.id)) {
+            Some(("slug", "Another post already uses that slug"))
+        } else {
+            None
+        };
+
+        match (fields, slug_error) {
+            (fields, None) => fields,
+            (Ok(_), Some(e)) => Err(vec![e]),
+            (Err(mut errors), Some(e)) => {
+                errors.push(e);
+                Err(errors)
+            }
+        }
+    }
+
+    fn draft(&self) -> Draft {
+        Draft {
+            title: self.title.clone(),
+            content: self.content.clone(),
+            slug: Some(self.slug.clone()),
+            tags: self.tags.clone(),
+            status: self.status.clone(),
+            publish_at: self.publish_at.clone(),
+        }
+    }
+}
+
+#[get("/e/<slug>")]
+fn edit_post(db: State<Pool>,
+             slug: String,
+             mut s: Session)
+             -> Option<Result<HTML<String>, rocket::response::Redirect>> {
+    let user = match s.user() {
+        Some(u) => u,
+        None => return Some(Err(rocket::response::Redirect::to("/in"))),
+    };
+
+    let conn = db.get().unwrap();
+    db::find_entry(&conn, &slug, true).map(|entry| {
+        let draft = Draft {
+            publish_at: entry.published_at
+                .map(|t| t.format(PUBLISH_AT_FORMAT).to_string())
+                .unwrap_or_default(),
+            status: String::from(entry.status.as_str()),
+            tags: entry.tags.join(", "),
+            title: entry.title,
+            content: entry.content,
+            slug: Some(entry.slug),
+        };
+        Ok(pages::editor::page(user,
+                               &s.csrf_token(),
+                               "Edit post",
+                               format!("/e/{}", slug),
+                               draft,
+                               vec![]))
+    })
+}
+
+#[post("/e/<slug>", data = "<edit>")]
+fn post_edit(db: State<Pool>,
+             slug: String,
+             s: Session,
+             edit: Form<Edit>)
+             -> Result<Option<Result<HTML<String>, rocket::response::Redirect>>,
+                       rocket::response::Failure> {
+    let user = match s.user() {
+        Some(u) => u,
+        None => return Ok(Some(Err(rocket::response::Redirect::to("/in")))),
+    };
+    let e = edit.get();
+    check_token(&s, &e.csrf)?;
+
+    let conn = db.get().unwrap();
+    let entry = match db::find_entry(&conn, &slug, true) {
+        Some(e) => e,
+        None => return Ok(None),
+    };
+
+    let fields = match e.fields(&conn, &entry) {
+        Ok(f) => f,
+        Err(errors) => {
+            return Ok(Some(Ok(pages::editor::page(user,
+                                                  &e.csrf,
+                                                  "Edit post",
+                                                  format!("/e/{}", slug),
+                                                  e.draft(),
+                                                  errors))));
+        }
+    };
+
+    db::update_entry(&conn, &entry, &e.slug, &fields, &user.0);
+    Ok(Some(Err(rocket::response::Redirect::to(format!("/r/{}", e.slug).as_str()))))
+}
+
+#[get("/h/<slug>")]
+fn history(db: State<Pool>,
+           slug: String,
+           mut s: Session)
+           -> Option<Result<HTML<String>, rocket::response::Redirect>> {
+    let user = match s.user() {
+        Some(u) => u,
+        None => return Some(Err(rocket::response::Redirect::to("/in"))),
+    };
+
+    let conn = db.get().unwrap();
+    db::find_entry(&conn, &slug, true).map(|entry| {
+        let revisions = db::revisions(&conn, &entry);
+        Ok(pages::history::page(user, &s.csrf_token(), entry, revisions))
+    })
+}
+
+#[post("/h/<slug>/<id>", data = "<token>")]
+fn restore_revision(db: State<Pool>,
+                    slug: String,
+                    id: i32,
+                    s: Session,
+                    token: Form<Token>)
+                    -> Result<Option<rocket::response::Redirect>, rocket::response::Failure> {
+    let user = match s.user() {
+        Some(u) => u,
+        None => return Ok(Some(rocket::response::Redirect::to("/in"))),
+    };
+    check_token(&s, &token.get().csrf)?;
+
+    let conn = db.get().unwrap();
+    let entry = match db::find_entry(&conn, &slug, true) {
+        Some(e) => e,
+        None => return Ok(None),
+    };
+    let revision = match db::find_revision(&conn, &entry, id) {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let fields = db::Fields {
+        title: &revision.title,
+        content: &revision.content,
+        status: entry.status,
+        published_at: entry.published_at,
+        tags: entry.tags.clone(),
+    };
+    db::update_entry(&conn, &entry, &entry.slug, &fields, &user.0);
+    Ok(Some(rocket::response::Redirect::to(format!("/h/{}", slug).as_str())))
+}
+
+#[get("/n")]
+fn new_post(mut s: Session) -> Result<HTML<String>, rocket::response::Redirect> {
+    match s.user() {
+        Some(u) => {
+            let draft = Draft { status: String::from("published"), ..Draft::default() };
+            Ok(pages::editor::page(u,
+                                   &s.csrf_token(),
+                                   "New post",
+                                   String::from("/n"),
+                                   draft,
+                                   vec![]))
+        }
+        None => Err(rocket::response::Redirect::to("/in")),
+    }
+}
+
+#[post("/n", data = "<post>")]
+fn post_new(db: State<Pool>,
+            s: Session,
+            post: Form<Post>)
+            -> Result<Result<HTML<String>, rocket::response::Redirect>, rocket::response::Failure> {
+    let user = match s.user() {
+        Some(u) => u,
+        None => return Ok(Err(rocket::response::Redirect::to("/in"))),
+    };
+    let p = post.get();
+    check_token(&s, &p.csrf)?;
+
+    let fields = match p.fields() {
+        Ok(f) => f,
+        Err(errors) => {
+            return Ok(Ok(pages::editor::page(user,
+                                             &p.csrf,
+                                             "New post",
+                                             String::from("/n"),
+                                             p.draft(),
+                                             errors)))
+        }
+    };
+
+    let conn = db.get().unwrap();
+    let slug = db::insert_entry(&conn, &fields, &user.0);
+    Ok(Err(rocket::response::Redirect::to(format!("/r/{}", slug).as_str())))
+}
+
+// Rocket treats a form POST whose first field is `_method` as that method, which is
+// how the delete button on each post reaches this. The form token has to come after it.
+#[delete("/d/<slug>", data = "<token>")]
+fn delete_post(db: State<Pool>,
+               slug: String,
+               s: Session,
+               token: Form<Token>)
+               -> Result<Option<rocket::response::Redirect>, rocket::response::Failure> {
+    if s.user().is_none() {
+        return Ok(Some(rocket::response::Redirect::to("/in")));
+    }
+    check_token(&s, &token.get().csrf)?;
+
+    let conn = db.get().unwrap();
+    if db::delete_entry(&conn, &slug) {
+        Ok(Some(rocket::response::Redirect::to("/")))
+    } else {
+        Ok(None)
+    }
+}
+
+#[derive(FromForm)]
+struct Preview { content: String }
+
+#[post("/p", data = "<preview>")]
+fn preview(s: Session, preview: Form<Preview>) -> Result<HTML<String>, rocket::response::Failure> {
+    match s.user() {
+        Some(_) => Ok(HTML(highlighting::markdown(preview.get().content.clone()))),
+        None => Err(rocket::response::Failure(rocket::http::Status::Unauthorized)),
+    }
+}
+
 fn main() {
-    mod_path! generated { concat!(env!("OUT_DIR"), "/generated.rs") }
     generated::load_files();
 
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1] == "export-static" {
+        let n = static_files::export::export(&args[2]).expect("Couldn't export static files");
+        println!("Exported {} files to {}", n, args[2]);
+        return;
+    }
+
     session::load_keys();
 
     let username = env!("PGUSER");
@@ -113,9 +592,23 @@ fn main() {
         .unwrap();
     let config = r2d2::Config::default();
     let pool = r2d2::Pool::new(config, manager).unwrap();
+    db::migrate(&pool.get().unwrap());
+
+    let page_size = env::var("PAGE_SIZE")
+        .ok()
+        .map(|s| s.parse().expect("PAGE_SIZE should be a number"))
+        .unwrap_or(10);
+    let site_url = env::var("SITE_URL").unwrap_or(String::from("https://jude.bio"));
 
     rocket::ignite()
         .manage(pool)
-        .mount("/", routes![home, get_favicon, get_static, static_qs, one, login, post_login])
+        .manage(PageSize(page_size))
+        .manage(SiteUrl(String::from(site_url.trim_right_matches('/'))))
+        .mount("/",
+               routes![home, home_qs, get_favicon, one, login, post_login, new_post, post_new,
+                       edit_post, post_edit, delete_post, preview, history, restore_revision,
+                       tagged, atom_feed, rss_feed, search, search_qs])
+        .mount(generated::MOUNT_POINT,
+               routes![get_static, static_qs])
         .launch()
 }
@@ -0,0 +1,168 @@
+use generated::asset_url;
+use highlighting::markdown;
+use maud::PreEscaped;
+use pages::*;
+use rocket::response::content::HTML;
+
+/// The contents of the post form, as last submitted.
+#[derive(Default)]
+pub struct Draft {
+    pub title: String,
+    pub content: String,
+    /// Only existing posts can have their slug edited; new ones get one from the title.
+    pub slug: Option<String>,
+    /// Comma-separated.
+    pub tags: String,
+    /// One of `draft`, `published` or `scheduled`.
+    pub status: String,
+    /// When a scheduled post goes up, as `YYYY-MM-DD HH:MM` in UTC.
+    pub publish_at: String,
+}
+
+const STATUSES: &'static [(&'static str, &'static str)] = &[("draft", "Draft"),
+                                                            ("published", "Published"),
+                                                            ("scheduled", "Scheduled")];
+
+/// A form error, as `(field, message)`.
+pub type FieldError = (&'static str, &'static str);
+
+fn error_for(errors: &[FieldError], field: &str) -> Option<&'static str> {
+    errors.iter().find(|e| e.0 == field).map(|e| e.1)
+}
+
+fn text_field(name: &'static str,
+              label: &'static str,
+              value: &str,
+              error: Option<&'static str>)
+              -> PreEscaped<String> {
+    html! {
+      div class={
+          "form-group"
+          @if error.is_some() {
+              " error"
+          }
+      } {
+          @if let Some(e) = error {
+              label.is-invalid-label for=(name) {
+                  (label)
+                  input.is-invalid-input type="text" name=(name) value=(value) /
+                  span.form-error.is-visible (e)
+              }
+          } @else {
+              label for=(name) {
+                  (label)
+                  input type="text" name=(name) value=(value) /
+              }
+          }
+      }
+    }
+}
+
+fn status_field(status: &str, error: Option<&'static str>) -> PreEscaped<String> {
+    let options = html! {
+      @for &(value, label) in STATUSES {
+        @if value == status {
+          option value=(value) selected? (label)
+        } @else {
+          option value=(value) (label)
+        }
+      }
+    };
+    html! {
+      div class={
+          "form-group"
+          @if error.is_some() {
+              " error"
+          }
+      } {
+          @if let Some(e) = error {
+              label.is-invalid-label for="status" {
+                  "Status"
+                  select.is-invalid-input name="status" (options)
+                  span.form-error.is-visible (e)
+              }
+          } @else {
+              label for="status" {
+                  "Status"
+                  select name="status" (options)
+              }
+          }
+      }
+    }
+}
+
+pub fn page(user: User,
+            csrf: &str,
+            heading: &'static str,
+            action: String,
+            draft: Draft,
+            errors: Vec<FieldError>)
+            -> HTML<String> {
+    let content_error = error_for(&errors, "content");
+    default_layout(Page {
+        title: Some(String::from(heading)),
+        user: Some(user),
+        body: html! {
+      article.bubble {
+        h3.form-title (heading)
+        form role="form" method="post" action=(action) {
+          input type="hidden" name="csrf" value=(csrf) /
+          (text_field("title", "Title", &draft.title, error_for(&errors, "title")))
+
+          @if let Some(ref slug) = draft.slug {
+              (text_field("slug", "Slug", slug, error_for(&errors, "slug")))
+          }
+
+          (text_field("tags", "Tags, separated by commas", &draft.tags, None))
+
+          div.row {
+            div class="large-6 columns" {
+              (status_field(&draft.status, error_for(&errors, "status")))
+            }
+            div class="large-6 columns" {
+              (text_field("publish_at",
+                          "Publish at (YYYY-MM-DD HH:MM, UTC)",
+                          &draft.publish_at,
+                          error_for(&errors, "publish_at")))
+            }
+          }
+
+          div.row {
+            div class="large-6 columns" {
+              div class={
+                  "form-group"
+                  @if content_error.is_some() {
+                      " error"
+                  }
+              } {
+                  @if let Some(e) = content_error {
+                      label.is-invalid-label for="content" {
+                          "Content"
+                          textarea.is-invalid-input name="content" rows="20"
+                              data-preview-source="/p" (draft.content)
+                          span.form-error.is-visible (e)
+                      }
+                  } @else {
+                      label for="content" {
+                          "Content"
+                          textarea name="content" rows="20" data-preview-source="/p"
+                              (draft.content)
+                      }
+                  }
+              }
+            }
+
+            div class="large-6 columns" {
+              span.preview-label "Preview"
+              div.preview.blog-post data-preview-target? {
+                (PreEscaped(markdown(draft.content.clone())))
+              }
+            }
+          }
+          button.button.small type="submit" "Save"
+        }
+      }
+      script src=(asset_url("js/preview.js")) {}
+    },
+    })
+}
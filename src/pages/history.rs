@@ -0,0 +1,107 @@
+use db::{Entry, Revision};
+use diff;
+use pages::*;
+use rocket::response::content::HTML;
+
+/// Unchanged lines further than this from a change are left out of diffs.
+const CONTEXT: usize = 2;
+
+/// A line diff from `old` to `new`, as `(class, line)` pairs. Runs of unchanged lines
+/// are collapsed into a single `skipped` line.
+fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<(&'static str, &'a str)> {
+    let lines: Vec<(&'static str, &'a str)> = diff::lines(old, new)
+        .into_iter()
+        .map(|d| match d {
+            diff::Result::Left(l) => ("removed", l),
+            diff::Result::Right(r) => ("added", r),
+            diff::Result::Both(l, _) => ("unchanged", l),
+        })
+        .collect();
+    let changed: Vec<usize> = lines.iter()
+        .enumerate()
+        .filter(|&(_, &(class, _))| class != "unchanged")
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut shown = Vec::new();
+    let mut skipping = false;
+    for (i, &(class, line)) in lines.iter().enumerate() {
+        if changed.iter().any(|&c| c <= i + CONTEXT && i <= c + CONTEXT) {
+            shown.push((class, line));
+            skipping = false;
+        } else if !skipping {
+            shown.push(("skipped", "…"));
+            skipping = true;
+        }
+    }
+    shown
+}
+
+fn revision_diff(previous: Option<&Revision>, revision: &Revision) -> PreEscaped<String> {
+    let (old_title, old_content) = match previous {
+        Some(p) => (p.title.as_str(), p.content.as_str()),
+        None => ("", ""),
+    };
+    let lines = diff_lines(old_content, &revision.content);
+    let unchanged = lines.iter().all(|&(class, _)| class == "skipped");
+    html! {
+      @if old_title != revision.title {
+        p.title-change {
+          @if !old_title.is_empty() {
+            del (old_title)
+            " → "
+          }
+          ins (revision.title)
+        }
+      }
+      @if unchanged {
+        p.no-changes "The text didn't change."
+      } @else {
+        pre.diff {
+          @for (class, line) in lines {
+            span class=(class) (line)
+            "\n"
+          }
+        }
+      }
+    }
+}
+
+pub fn page(user: User, csrf: &str, entry: Entry, revisions: Vec<Revision>) -> HTML<String> {
+    default_layout(Page {
+        title: Some(format!("History of {}", entry.title)),
+        user: Some(user),
+        body: html! {
+      article.bubble.blog-post.history {
+        h1.post-title {
+          "History of "
+          a href={ "/r/" (entry.slug) } (entry.title)
+        }
+        @for (i, revision) in revisions.iter().enumerate() {
+          section.revision {
+            h3 {
+              (revision.created_at.format("%Y-%m-%d %H:%M UTC").to_string())
+              " by "
+              (revision.author.as_ref().map(|a| a.as_str()).unwrap_or("unknown"))
+              @if i == 0 {
+                " "
+                span.status-label "Current"
+              } @else {
+                " "
+                form.restore-form method="post"
+                    action={ "/h/" (entry.slug) "/" (revision.id) } {
+                  input type="hidden" name="csrf" value=(csrf) /
+                  button.button.tiny type="submit"
+                      data-confirm="Replace the current text with this version?" {
+                    "Restore"
+                  }
+                }
+              }
+            }
+            (revision_diff(revisions.get(i + 1), revision))
+          }
+        }
+      }
+    },
+    })
+}
@@ -2,7 +2,27 @@ use db::Entry;
 use pages::*;
 use rocket::response::content::HTML;
 
-pub fn page(u: Option<User>, es: Vec<Entry>) -> HTML<String> {
+/// A bubble linking to each entry.
+pub fn previews(es: Vec<Entry>) -> PreEscaped<String> {
+    html! {
+      @for entry in es {
+        article.bubble.preview-bubble {
+          h3.post-preview {
+            a.post-title href={ "/r/" (entry.slug) } (entry.title)
+            (status_label(&entry))
+          }
+          (tag_links(&entry.tags))
+        }
+      }
+    }
+}
+
+/// `newer` and `older` link to the neighbouring pages, if there are any.
+pub fn page(u: Option<User>,
+            es: Vec<Entry>,
+            newer: Option<String>,
+            older: Option<String>)
+            -> HTML<String> {
     default_layout(Page {
         title: None,
         user: u,
@@ -11,10 +31,15 @@ pub fn page(u: Option<User>, es: Vec<Entry>) -> HTML<String> {
         h5.site-title "I’m Jude, a functional programmer with a colorful head."
       }
 
-      @for entry in es {
-        article.bubble.preview-bubble {
-          h3.post-preview {
-            a.post-title href={ "/r/" (entry.slug) } (entry.title)
+      (previews(es))
+
+      @if newer.is_some() || older.is_some() {
+        div.pages {
+          @if let Some(href) = newer {
+            a.newer href=(href) "← Newer"
+          }
+          @if let Some(href) = older {
+            a.older href=(href) "Older →"
           }
         }
       }
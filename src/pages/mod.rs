@@ -1,3 +1,5 @@
+use db::{Entry, Status};
+use generated::asset_url;
 use maud::DOCTYPE;
 use maud::PreEscaped;
 use rocket::response::content::HTML;
@@ -5,6 +7,10 @@ use rocket::response::content::HTML;
 pub mod home;
 pub mod one;
 pub mod login;
+pub mod editor;
+pub mod history;
+pub mod search;
+pub mod tag;
 
 pub struct User(pub String);
 
@@ -14,6 +20,33 @@ pub struct Page {
     pub user: Option<User>,
 }
 
+/// A note on entries that visitors can't see yet.
+pub fn status_label(entry: &Entry) -> PreEscaped<String> {
+    let label = match (entry.status, entry.published_at) {
+        (Status::Draft, _) => String::from("Draft"),
+        (_, Some(t)) => format!("Scheduled for {}", t.format("%Y-%m-%d %H:%M UTC")),
+        (_, None) => String::from("Unpublished"),
+    };
+    html! {
+      @if !entry.is_public() {
+        " "
+        span.status-label (label)
+      }
+    }
+}
+
+pub fn tag_links(tags: &[String]) -> PreEscaped<String> {
+    html! {
+      @if !tags.is_empty() {
+        ul.tags {
+          @for tag in tags {
+            li a href={ "/t/" (tag) } (tag)
+          }
+        }
+      }
+    }
+}
+
 pub fn default_layout(page: Page) -> HTML<String> {
     HTML(html! {
     (DOCTYPE)
@@ -23,9 +56,12 @@ pub fn default_layout(page: Page) -> HTML<String> {
         meta http-equiv="X-UA-Compatible" content="IE=edge" /
         meta name="viewport" content="width=device-width,initial-scale=1" /
 
-        link rel="shortcut icon" href="/s/favicon.ico" /
+        link rel="shortcut icon" href=(asset_url("favicon.ico")) /
+
+        link rel="stylesheet" href=(asset_url("css/all.css")) type="text/css" /
 
-        link rel="stylesheet" href="/s/css/all.css" type="text/css" /
+        link rel="alternate" type="application/atom+xml" title="jude.bio" href="/feed.atom" /
+        link rel="alternate" type="application/rss+xml" title="jude.bio" href="/feed.rss" /
 
         (PreEscaped("<!--[if lt IE 9]>"))
         (PreEscaped("<script src=\"http://html5shiv.googlecode.com/svn/trunk/html5.js\"></script>"))
@@ -55,6 +91,9 @@ pub fn default_layout(page: Page) -> HTML<String> {
                   a.dot#new-post href="/n" title="Make a new post" "New post"
                 }
               }
+              form.search-form role="search" method="get" action="/search" {
+                input type="search" name="q" placeholder="Search" aria-label="Search" /
+              }
             }
             (page.body)
             footer {
@@ -3,7 +3,8 @@ use highlighting::markdown;
 use pages::*;
 use rocket::response::content::HTML;
 
-pub fn page(user: Option<User>, entry: Entry) -> HTML<String> {
+/// `csrf` is the session's form token, for the delete button.
+pub fn page(user: Option<User>, csrf: &str, entry: Entry) -> HTML<String> {
     let loggedin = user.is_some();
     default_layout(Page {
         title: None,
@@ -12,17 +13,22 @@ pub fn page(user: Option<User>, entry: Entry) -> HTML<String> {
       article.bubble.blog-post {
         h1.post-title {
             (entry.title)
+            (status_label(&entry))
             @if loggedin {
                 " "
                 a.edit-link.fa.fa-pencil href={ "/e/" (entry.slug) } {}
                 " "
+                a.history-link.fa.fa-history href={ "/h/" (entry.slug) } title="History" {}
+                " "
                 form.delete-form method="post" action={ "/d/" (entry.slug) } {
                     input type="hidden" name="_method" value="DELETE" /
+                    input type="hidden" name="csrf" value=(csrf) /
                     button.fa.fa-trash-o type="submit"
                         data-confirm="Are you sure you want to delete this post?" {}
                 }
             }
         }
+        (tag_links(&entry.tags))
         (PreEscaped(markdown(entry.content)))
       }
     },
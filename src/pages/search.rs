@@ -0,0 +1,57 @@
+use db::{Entry, MATCH_END, MATCH_START};
+use pages::*;
+use rocket::response::content::HTML;
+
+/// Split a snippet from `db::search` into runs of text, each marked as to whether it
+/// matched the query.
+fn matches(snippet: &str) -> Vec<(bool, &str)> {
+    let mut runs = Vec::new();
+    let mut rest = snippet;
+    while let Some(start) = rest.find(MATCH_START) {
+        runs.push((false, &rest[..start]));
+        rest = &rest[start + MATCH_START.len_utf8()..];
+        let end = rest.find(MATCH_END).unwrap_or(rest.len());
+        runs.push((true, &rest[..end]));
+        rest = &rest[end..];
+        if rest.starts_with(MATCH_END) {
+            rest = &rest[MATCH_END.len_utf8()..];
+        }
+    }
+    runs.push((false, rest));
+    runs
+}
+
+pub fn page(u: Option<User>, query: &str, results: Vec<(Entry, String)>) -> HTML<String> {
+    default_layout(Page {
+        title: Some(format!("Search for {}", query)),
+        user: u,
+        body: html! {
+      article.bubble.last-bubble {
+        form.search-page-form role="search" method="get" action="/search" {
+          input type="search" name="q" value=(query) placeholder="Search" aria-label="Search" /
+        }
+      }
+
+      @if !query.trim().is_empty() && results.is_empty() {
+        p.pages "Nothing matched."
+      }
+      @for &(ref entry, ref snippet) in &results {
+        article.bubble.preview-bubble.search-result {
+          h3.post-preview {
+            a.post-title href={ "/r/" (entry.slug) } (entry.title)
+            (status_label(entry))
+          }
+          p.snippet {
+            @for (marked, text) in matches(snippet) {
+              @if marked {
+                mark (text)
+              } @else {
+                (text)
+              }
+            }
+          }
+        }
+      }
+    },
+    })
+}
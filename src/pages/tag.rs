@@ -0,0 +1,24 @@
+use db::Entry;
+use pages::*;
+use pages::home::previews;
+use rocket::response::content::HTML;
+
+pub fn page(u: Option<User>, tag: &str, es: Vec<Entry>) -> HTML<String> {
+    default_layout(Page {
+        title: Some(format!("Posts tagged {}", tag)),
+        user: u,
+        body: html! {
+      article.bubble.last-bubble {
+        h5.site-title {
+          "Posts tagged "
+          strong (tag)
+        }
+      }
+
+      @if es.is_empty() {
+        p.pages "Nothing here yet."
+      }
+      (previews(es))
+    },
+    })
+}
@@ -11,7 +11,9 @@ use rustc_serialize::base64;
 use rustc_serialize::json;
 use sodiumoxide::crypto::secretbox::{Nonce, Key};
 use sodiumoxide::crypto::secretbox;
+use sodiumoxide::utils;
 use state::LocalStorage;
+use static_files::AccessGuard;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
@@ -22,6 +24,9 @@ use pages;
 
 const COOKIE_NAME: &'static str = "_SESSION";
 
+/// Where the session keeps the token its forms carry.
+const CSRF_KEY: &'static str = "csrf";
+
 static _KN: LocalStorage<(Key, Nonce)> = LocalStorage::new();
 
 pub fn load_keys() {
@@ -81,9 +86,33 @@ impl<'a> Session<'a> {
     }
 
     pub fn set_user(&mut self, u: pages::User) -> Option<Vec<u8>> {
+        // Logging in starts over with a new form token.
+        self._store.remove(CSRF_KEY);
         self.insert(String::from("user"), u.0.as_bytes())
     }
 
+    /// The token that forms which change anything include, so handlers can tell that
+    /// a submission came from one of this site's pages rather than another site's.
+    /// It's made the first time it's asked for.
+    pub fn csrf_token(&mut self) -> String {
+        if let Some(t) = self.get_string(CSRF_KEY) {
+            return t;
+        }
+        let mut bytes = [0u8; 32];
+        thread_rng().fill_bytes(&mut bytes);
+        let token = bytes.to_base64(base64::URL_SAFE);
+        self.insert(String::from(CSRF_KEY), token.as_bytes());
+        token
+    }
+
+    /// Whether `token`, as submitted with a form, is this session's form token.
+    pub fn check_csrf(&self, token: &str) -> bool {
+        match self.get(CSRF_KEY) {
+            Some(t) => !t.is_empty() && utils::memcmp(t, token.as_bytes()),
+            None => false,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn insert<R>(&mut self, key: String, value: R) -> Option<Vec<u8>>
         where R: Into<Vec<u8>>
@@ -100,21 +129,24 @@ impl<'a> Session<'a> {
     }
 
     fn from_cookie(cj: &'a Cookies, s: String) -> Option<Session<'a>> {
-        let &(ref k, ref n) = _KN.get();
-        s.from_base64()
-            .ok()
-            .and_then(|bytes| secretbox::open(&bytes, n, k).ok())
-            .and_then(|plaintext| String::from_utf8(plaintext).ok())
-            .and_then(|string| json::decode(string.as_str()).ok())
-            .map(|store| {
-                Session {
-                    _store: store,
-                    _cookiejar: cj,
-                }
-            })
+        decode(s).map(|store| {
+            Session {
+                _store: store,
+                _cookiejar: cj,
+            }
+        })
     }
 }
 
+fn decode(s: String) -> Option<HashMap<String, Vec<u8>>> {
+    let &(ref k, ref n) = _KN.get();
+    s.from_base64()
+        .ok()
+        .and_then(|bytes| secretbox::open(&bytes, n, k).ok())
+        .and_then(|plaintext| String::from_utf8(plaintext).ok())
+        .and_then(|string| json::decode(string.as_str()).ok())
+}
+
 impl<'a> Drop for Session<'a> {
     fn drop(&mut self) {
         self._cookiejar.add(Cookie::new(COOKIE_NAME, self.to_cookie()));
@@ -131,3 +163,28 @@ impl<'r, 'a> FromRequest<'a, 'r> for Session<'a> {
             .unwrap_or(Session::new(cookiejar)))
     }
 }
+
+/// The logged-in user, read from the session cookie without writing it back as
+/// `Session` does, so responses that use it can still be cached by proxies.
+pub struct CurrentUser(pub Option<pages::User>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for CurrentUser {
+    type Error = ();
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, (Status, ()), ()> {
+        Outcome::Success(CurrentUser(req.cookies()
+            .find(COOKIE_NAME)
+            .and_then(|cookie| decode(String::from(cookie.value())))
+            .and_then(|store| store.get("user").and_then(|u| String::from_utf8(u.clone()).ok()))
+            .map(pages::User)))
+    }
+}
+
+impl AccessGuard for CurrentUser {
+    fn allows(&self, condition: &str) -> bool {
+        match condition {
+            "logged_in" => self.0.is_some(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+(function () {
+  var source = document.querySelector('[data-preview-source]');
+  var target = document.querySelector('[data-preview-target]');
+  if (!source || !target) {
+    return;
+  }
+
+  var url = source.getAttribute('data-preview-source');
+  var timer = null;
+  var pending = null;
+
+  function refresh() {
+    if (pending) {
+      pending.abort();
+    }
+
+    var xhr = new XMLHttpRequest();
+    xhr.open('POST', url);
+    xhr.setRequestHeader('Content-Type', 'application/x-www-form-urlencoded');
+    xhr.onload = function () {
+      if (xhr.status === 200) {
+        target.innerHTML = xhr.responseText;
+      }
+      pending = null;
+    };
+    xhr.send('content=' + encodeURIComponent(source.value));
+    pending = xhr;
+  }
+
+  source.addEventListener('input', function () {
+    clearTimeout(timer);
+    timer = setTimeout(refresh, 300);
+  });
+})();
@@ -0,0 +1,155 @@
+//! Size limits on processed assets.
+
+use compress;
+use file::StaticFile;
+use std::cmp;
+
+#[derive(Clone, Debug)]
+pub struct Budgets {
+    pub files: Vec<(String, usize)>,
+    pub total: Option<usize>,
+    /// Measure gzipped sizes rather than raw ones.
+    pub compressed: bool,
+}
+
+impl Budgets {
+    pub fn new() -> Budgets {
+        Budgets {
+            files: Vec::new(),
+            total: None,
+            compressed: false,
+        }
+    }
+
+    /// Check `files` against the budgets, returning a table of the offenders if any
+    /// are over, or the names of any budgeted assets that aren't among `files`.
+    pub fn check(&self, files: &[StaticFile]) -> Result<(), String> {
+        if self.files.is_empty() && self.total.is_none() {
+            return Ok(());
+        }
+
+        let sizes: Vec<(&str, usize)> = files.iter()
+            .map(|f| {
+                let size = if self.compressed {
+                    compress::gzip(&f.bytes).len()
+                } else {
+                    f.bytes.as_ref().len()
+                };
+                (f.name.as_str(), size)
+            })
+            .collect();
+
+        let mut offenders = Vec::new();
+        let mut unknown = Vec::new();
+        for &(ref name, limit) in &self.files {
+            match sizes.iter().find(|&&(n, _)| n == name.as_str()) {
+                Some(&(_, size)) if size > limit => offenders.push((name.clone(), size, limit)),
+                Some(_) => {}
+                None => unknown.push(format!("{:?}", name)),
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(format!("size budgets given for unknown assets: {}", unknown.join(", ")));
+        }
+        if let Some(limit) = self.total {
+            let size = sizes.iter().fold(0, |acc, &(_, s)| acc + s);
+            if size > limit {
+                offenders.push((String::from("(total)"), size, limit));
+            }
+        }
+
+        if offenders.is_empty() {
+            return Ok(());
+        }
+
+        let width = cmp::max(offenders.iter().map(|o| o.0.len()).max().unwrap_or(0), 5);
+        let mut table = format!("assets over budget{}:\n{:<w$}  {:>10}  {:>10}\n",
+                                if self.compressed { " (gzipped)" } else { "" },
+                                "asset",
+                                "size",
+                                "budget",
+                                w = width);
+        for (name, size, limit) in offenders {
+            table.push_str(format!("{:<w$}  {:>10}  {:>10}\n", name, size, limit, w = width)
+                .as_str());
+        }
+        Err(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budgets;
+    use file::{ByteString, StaticFile};
+    use mime;
+
+    fn file(name: &str, size: usize) -> StaticFile {
+        StaticFile {
+            name: String::from(name),
+            bytes: ByteString::Dynamic(vec![b'a'; size]),
+            mime: mime::content_type("text/plain"),
+            etag: String::new(),
+            headers: Vec::new(),
+            condition: None,
+        }
+    }
+
+    fn files() -> Vec<StaticFile> {
+        vec![file("css/all.css", 100), file("js/app.js", 10)]
+    }
+
+    #[test]
+    fn no_budgets_pass() {
+        assert_eq!(Budgets::new().check(&files()), Ok(()));
+    }
+
+    #[test]
+    fn files_within_budget_pass() {
+        let mut budgets = Budgets::new();
+        budgets.files.push((String::from("css/all.css"), 100));
+        budgets.total = Some(110);
+        assert_eq!(budgets.check(&files()), Ok(()));
+    }
+
+    #[test]
+    fn offenders_are_tabulated() {
+        let mut budgets = Budgets::new();
+        budgets.files.push((String::from("css/all.css"), 50));
+        budgets.files.push((String::from("js/app.js"), 50));
+        assert_eq!(budgets.check(&files()),
+                   Err(String::from("assets over budget:\n\
+                                     asset              size      budget\n\
+                                     css/all.css         100          50\n")));
+    }
+
+    #[test]
+    fn total_gets_its_own_row() {
+        let mut budgets = Budgets::new();
+        budgets.files.push((String::from("css/all.css"), 50));
+        budgets.total = Some(100);
+        assert_eq!(budgets.check(&files()),
+                   Err(String::from("assets over budget:\n\
+                                     asset              size      budget\n\
+                                     css/all.css         100          50\n\
+                                     (total)             110         100\n")));
+    }
+
+    #[test]
+    fn short_names_keep_the_header_width() {
+        let mut budgets = Budgets::new();
+        budgets.files.push((String::from("a.js"), 5));
+        assert_eq!(budgets.check(&[file("a.js", 10)]),
+                   Err(String::from("assets over budget:\n\
+                                     asset        size      budget\n\
+                                     a.js           10           5\n")));
+    }
+
+    #[test]
+    fn unknown_budget_names_are_errors() {
+        let mut budgets = Budgets::new();
+        budgets.files.push((String::from("css/missing.css"), 50));
+        assert_eq!(budgets.check(&files()),
+                   Err(String::from("size budgets given for unknown assets: \
+                                     \"css/missing.css\"")));
+    }
+}
@@ -0,0 +1,155 @@
+//! A content-addressed cache of processor output, so that release builds only rerun
+//! sass when a stylesheet or one of its imports has changed.
+//!
+//! Each output is stored under its slot, which identifies the file it was made for, and
+//! its key, which covers everything that went into it. Only the latest key is kept for
+//! each slot.
+
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use file;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P>(dir: P) -> Cache
+        where P: Into<PathBuf>
+    {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).expect(format!("{:?} couldn't be created", dir).as_str());
+        Cache { dir: dir }
+    }
+
+    /// The slot for the file registered as `name` and made from `source`.
+    pub fn slot(name: &str, source: &str) -> String {
+        let mut m = Md5::new();
+        m.input_str(name);
+        m.input_str("\0");
+        m.input_str(source);
+        m.result_str().chars().take(16).collect()
+    }
+
+    fn path(&self, slot: &str, key: &str) -> PathBuf {
+        self.dir.join(format!("{}-{}", slot, key))
+    }
+
+    /// The cached output in `slot` for `key`, if there is any.
+    pub fn get(&self, slot: &str, key: &str) -> Option<Vec<u8>> {
+        let mut f = match fs::File::open(self.path(slot, key)) {
+            Ok(f) => f,
+            Err(_) => return None,
+        };
+        let mut v = Vec::new();
+        if f.read_to_end(&mut v).is_ok() {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    /// Store `bytes` as the output in `slot` for `key`, removing the slot's output for
+    /// any other key. Failing to do either only costs a rebuild or some disk space, so
+    /// errors are ignored.
+    pub fn put(&self, slot: &str, key: &str, bytes: &[u8]) {
+        let path = self.path(slot, key);
+        let tmp = path.with_extension("tmp");
+        let written = fs::File::create(&tmp).and_then(|mut f| f.write_all(bytes));
+        if written.is_err() || fs::rename(&tmp, &path).is_err() {
+            return;
+        }
+
+        let prefix = format!("{}-", slot);
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let stale = entry.file_name()
+                    .to_str()
+                    .map(|n| n.starts_with(prefix.as_str()) && entry.path() != path)
+                    .unwrap_or(false);
+                if stale {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+
+    /// The key for compiling the stylesheet at `path` with `args`.
+    ///
+    /// sass doesn't report what a stylesheet imports, so this covers every stylesheet
+    /// in its directory and in each load path given in `args`, along with the sass
+    /// version and the arguments themselves.
+    pub fn sass_key(&self, path: &str, args: &[String], base_url: &str) -> String {
+        let mut m = Md5::new();
+        m.input_str("sass\0");
+        m.input_str(file::sass_version().as_str());
+        m.input_str("\0");
+        m.input_str(base_url);
+        for arg in args {
+            m.input_str("\0");
+            m.input_str(arg.as_str());
+        }
+
+        let mut dirs: Vec<PathBuf> = load_paths(args).into_iter().map(PathBuf::from).collect();
+        if let Some(parent) = Path::new(path).parent() {
+            dirs.push(parent.to_path_buf());
+        }
+
+        let mut inputs = vec![PathBuf::from(path)];
+        for dir in dirs {
+            walk(&dir, &mut inputs);
+        }
+        for input in inputs {
+            let mut bytes = Vec::new();
+            if let Ok(mut f) = fs::File::open(&input) {
+                f.read_to_end(&mut bytes)
+                    .expect(format!("{:?} couldn't be read", input).as_str());
+            }
+            m.input_str(format!("\0{}\0{}\0", input.display(), bytes.len()).as_str());
+            m.input(bytes.as_slice());
+        }
+
+        m.result_str()
+    }
+}
+
+/// The directories given to sass with `-I` or `--load-path`.
+fn load_paths(args: &[String]) -> Vec<&str> {
+    let mut paths = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-I" || arg == "--load-path" {
+            if let Some(p) = iter.next() {
+                paths.push(p.as_str());
+            }
+        } else if arg.starts_with("--load-path=") {
+            paths.push(&arg["--load-path=".len()..]);
+        } else if arg.starts_with("-I") {
+            paths.push(&arg[2..]);
+        }
+    }
+    paths
+}
+
+/// Add every stylesheet under `dir` to `out`, in a stable order.
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            walk(&entry, out);
+        } else {
+            match entry.extension().and_then(|e| e.to_str()) {
+                Some("scss") | Some("sass") | Some("css") => out.push(entry),
+                _ => {}
+            }
+        }
+    }
+}
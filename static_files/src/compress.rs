@@ -0,0 +1,14 @@
+//! Precompression of assets.
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+/// Gzip `bytes` at the best compression level.
+pub fn gzip<T>(bytes: T) -> Vec<u8>
+    where T: AsRef<[u8]>
+{
+    let mut e = GzEncoder::new(Vec::new(), Compression::Best);
+    e.write_all(bytes.as_ref()).expect("gzip failed");
+    e.finish().expect("gzip failed")
+}
@@ -0,0 +1,225 @@
+use budget::Budgets;
+use file::Dir;
+use headers::HeaderPolicy;
+use mime;
+use std::path::{Path, PathBuf};
+
+/// Build-time configuration shared by the build script, the stylesheets, the generated
+/// module and the app's routes.
+#[derive(Clone, Debug)]
+pub struct Config {
+    bundle: Option<String>,
+    prefix: String,
+    cdn: Option<String>,
+    manifest: Option<PathBuf>,
+    budgets: Budgets,
+    mime_types: Vec<(String, String)>,
+    headers: HeaderPolicy,
+    cache_control: Option<String>,
+    dev_dirs: Vec<Dir>,
+    sass_version: Option<String>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Files mounted at `/s`, served from the same origin.
+    pub fn new() -> Config {
+        Config {
+            bundle: None,
+            prefix: String::from("/s"),
+            cdn: None,
+            manifest: None,
+            budgets: Budgets::new(),
+            mime_types: Vec::new(),
+            headers: HeaderPolicy::new(),
+            cache_control: None,
+            dev_dirs: Vec::new(),
+            sass_version: None,
+            cache_dir: None,
+        }
+    }
+
+    /// Generate a separate bundle called `name`, written to `$OUT_DIR/<name>.rs` with
+    /// its own store, rather than the default `generated.rs` that fills `FILES`.
+    pub fn bundle<S>(mut self, name: S) -> Config
+        where S: Into<String>
+    {
+        self.bundle = Some(name.into());
+        self
+    }
+
+    pub fn bundle_name(&self) -> Option<&str> {
+        self.bundle.as_ref().map(|b| b.as_str())
+    }
+
+    /// Set the `Cache-Control` header this bundle's files are served with. This takes
+    /// precedence over the one in the `headers` policy, regardless of call order.
+    pub fn cache_control<S>(mut self, value: S) -> Config
+        where S: Into<String>
+    {
+        self.cache_control = Some(value.into());
+        self
+    }
+
+    pub fn cache_control_value(&self) -> Option<&str> {
+        self.cache_control.as_ref().map(|c| c.as_str())
+    }
+
+    /// Set the path the app mounts its static routes at, e.g. `/assets`.
+    pub fn prefix<S>(mut self, prefix: S) -> Config
+        where S: Into<String>
+    {
+        let p = prefix.into();
+        self.prefix = format!("/{}", p.trim_matches('/'));
+        self
+    }
+
+    /// Have pages and stylesheets refer to assets on another origin, e.g.
+    /// `https://cdn.example.com/s/`. The app still serves them under its prefix, so the
+    /// CDN can pull from it.
+    pub fn cdn<S>(mut self, url: S) -> Config
+        where S: Into<String>
+    {
+        let u = url.into();
+        self.cdn = Some(format!("{}/", u.trim_right_matches('/')));
+        self
+    }
+
+    /// Also write the asset manifest to `path`. Manifests are only written by release
+    /// builds, which always put one in `$OUT_DIR` as `manifest.json`, or as
+    /// `<bundle>-manifest.json` for bundles named with `bundle`.
+    pub fn manifest<P>(mut self, path: P) -> Config
+        where P: Into<PathBuf>
+    {
+        self.manifest = Some(path.into());
+        self
+    }
+
+    /// Where to write the asset manifest besides `$OUT_DIR`, if anywhere.
+    pub fn manifest_path(&self) -> Option<&Path> {
+        self.manifest.as_ref().map(|p| p.as_path())
+    }
+
+    /// Fail the build if the processed file registered as `name` is over `bytes` long.
+    pub fn budget<S>(mut self, name: S, bytes: usize) -> Config
+        where S: Into<String>
+    {
+        self.budgets.files.push((name.into(), bytes));
+        self
+    }
+
+    /// Fail the build if all the processed files together are over `bytes` long.
+    pub fn total_budget(mut self, bytes: usize) -> Config {
+        self.budgets.total = Some(bytes);
+        self
+    }
+
+    /// Check budgets against gzipped sizes, which is what most clients download.
+    pub fn compressed_budgets(mut self) -> Config {
+        self.budgets.compressed = true;
+        self
+    }
+
+    pub fn budgets(&self) -> &Budgets {
+        &self.budgets
+    }
+
+    /// Serve files ending in `.ext` as `mime`, unless they were given a type with
+    /// `File::mime`. This takes precedence over the built-in table in `mime`.
+    pub fn mime_type<S, T>(mut self, ext: S, mime: T) -> Config
+        where S: Into<String>,
+              T: Into<String>
+    {
+        let e = ext.into();
+        self.mime_types.push((e.trim_left_matches('.').to_lowercase(), mime.into()));
+        self
+    }
+
+    /// The type configured with `mime_type` for a file named `name`, if any.
+    pub fn mime_for(&self, name: &str) -> Option<&str> {
+        mime::from_table(&self.mime_types, name)
+    }
+
+    /// Choose which security headers static files are served with. The default is
+    /// `HeaderPolicy::new()`.
+    pub fn headers(mut self, policy: HeaderPolicy) -> Config {
+        self.headers = policy;
+        self
+    }
+
+    pub fn header_policy(&self) -> &HeaderPolicy {
+        &self.headers
+    }
+
+    /// In development, serve any file under `root` as `prefix/<path>`, compiling
+    /// stylesheets with `sass_args`. Registered files take precedence.
+    pub fn dev_dir<S, P, T>(mut self, prefix: S, root: P, sass_args: Vec<T>) -> Config
+        where S: Into<String>,
+              P: Into<String>,
+              T: Into<String>
+    {
+        self.dev_dirs.push(Dir {
+            prefix: prefix.into(),
+            root: root.into(),
+            sass_args: sass_args.into_iter().map(|x| x.into()).collect(),
+            mime_types: Vec::new(),
+        });
+        self
+    }
+
+    /// The directories given to `dev_dir`, each carrying the types from `mime_type`.
+    pub fn dev_dirs(&self) -> Vec<Dir> {
+        self.dev_dirs
+            .iter()
+            .map(|d| Dir { mime_types: self.mime_types.clone(), ..d.clone() })
+            .collect()
+    }
+
+    /// Fail the build unless `sass --version` mentions `version`, since different
+    /// versions of sass produce different output from the same stylesheets.
+    pub fn sass_version<S>(mut self, version: S) -> Config
+        where S: Into<String>
+    {
+        self.sass_version = Some(version.into());
+        self
+    }
+
+    pub fn required_sass_version(&self) -> Option<&str> {
+        self.sass_version.as_ref().map(|v| v.as_str())
+    }
+
+    /// Keep processor output between builds in `dir`, rather than in
+    /// `$OUT_DIR/asset-cache`. A directory shared between profiles, such as one under
+    /// `target/`, saves rerunning sass when switching between them.
+    pub fn cache_dir<P>(mut self, dir: P) -> Config
+        where P: Into<PathBuf>
+    {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    pub fn cache_path(&self) -> Option<&Path> {
+        self.cache_dir.as_ref().map(|p| p.as_path())
+    }
+
+    /// The path to mount the static routes at, without a trailing slash.
+    pub fn mount_point(&self) -> &str {
+        self.prefix.as_str()
+    }
+
+    /// The URL assets are referred to by, with a trailing slash. This is the CDN base
+    /// URL if there is one and the mount point otherwise.
+    pub fn base_url(&self) -> String {
+        match self.cdn {
+            Some(ref c) => c.clone(),
+            None if self.prefix == "/" => String::from("/"),
+            None => format!("{}/", self.prefix),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
@@ -0,0 +1,255 @@
+//! Post-processing of compiled stylesheets.
+//!
+//! Stylesheets refer to other assets with `url(...)`, either absolutely under the
+//! base URL (as the `static()` sass function does) or relative to the stylesheet
+//! itself (as font-awesome does with `$fa-font-path`). In production these names are
+//! rewritten to the fingerprinted names the assets are served under.
+
+use std::collections::HashMap;
+
+/// Rewrite every local `url(...)` and `@import "..."` in `css`, which is served as
+/// `name`. Comments and other strings are left alone, and quoted urls may contain `)`.
+///
+/// `base_url` is the URL assets are referred to by, as passed to sass. `assets` maps
+/// logical file names to the names they should be referenced by.
+/// References to anything not in `assets` are returned as errors, one per reference.
+pub fn rewrite_urls(name: &str,
+                    css: &str,
+                    base_url: &str,
+                    assets: &HashMap<String, String>)
+                    -> Result<String, Vec<String>> {
+    let bytes = css.as_bytes();
+    let mut out = String::with_capacity(css.len());
+    let mut errors = Vec::new();
+    // Everything before `copied` is in `out` already. Indices only ever land on ASCII
+    // delimiters, so slicing `css` at them is safe.
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") {
+            i = find(bytes, i + 2, b"*/").map(|e| e + 2).unwrap_or(bytes.len());
+        } else if bytes[i] == b'"' || bytes[i] == b'\'' {
+            i = string_end(bytes, i);
+        } else if starts_word(bytes, i, b"url(") {
+            let open = i + 4;
+            match url_token(css, open) {
+                Some((url, close)) => {
+                    if let Some(replacement) = rewrite(name, url, base_url, assets, &mut errors) {
+                        out.push_str(&css[copied..open]);
+                        out.push_str(&replacement);
+                        out.push(')');
+                        copied = close;
+                    }
+                    i = close;
+                }
+                None => i = bytes.len(),
+            }
+        } else if starts_word(bytes, i, b"@import") {
+            let start = skip_space(bytes, i + 7);
+            i = start;
+            if start < bytes.len() && (bytes[start] == b'"' || bytes[start] == b'\'') {
+                let end = string_end(bytes, start);
+                if end - start >= 2 && bytes[end - 1] == bytes[start] {
+                    let url = &css[start + 1..end - 1];
+                    if let Some(replacement) = rewrite(name, url, base_url, assets, &mut errors) {
+                        out.push_str(&css[copied..start]);
+                        out.push_str(&replacement);
+                        copied = end;
+                    }
+                }
+                i = end;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    out.push_str(&css[copied..]);
+
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The quoted reference `url` should be rewritten to, or `None` to leave it as it is.
+/// Unknown assets are added to `errors`.
+fn rewrite(name: &str,
+           url: &str,
+           base_url: &str,
+           assets: &HashMap<String, String>,
+           errors: &mut Vec<String>)
+           -> Option<String> {
+    let (logical, suffix) = match resolve(name, url, base_url) {
+        Some(r) => r,
+        None => return None,
+    };
+    match assets.get(&logical) {
+        Some(target) => Some(format!("\"{}{}{}\"", base_url, target, suffix)),
+        None => {
+            errors.push(format!("{}: reference to unknown asset {:?}", name, url));
+            None
+        }
+    }
+}
+
+/// Whether `word` starts at `i`, and isn't the tail of a longer identifier.
+fn starts_word(bytes: &[u8], i: usize, word: &[u8]) -> bool {
+    let boundary = i == 0 || {
+        let c = bytes[i - 1];
+        !(c >= 0x80 || (c as char).is_alphanumeric() || c == b'-' || c == b'_')
+    };
+    boundary && bytes[i..].starts_with(word)
+}
+
+fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    (from..bytes.len()).find(|&i| bytes[i..].starts_with(needle))
+}
+
+fn skip_space(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// The index just past the string whose opening quote is at `start`.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The url inside a `url(` whose contents start at `open`, and the index just past the
+/// closing `)`. `None` if it isn't closed.
+fn url_token(css: &str, open: usize) -> Option<(&str, usize)> {
+    let bytes = css.as_bytes();
+    let start = skip_space(bytes, open);
+    if start < bytes.len() && (bytes[start] == b'"' || bytes[start] == b'\'') {
+        let end = string_end(bytes, start);
+        let close = skip_space(bytes, end);
+        if end - start < 2 || bytes[end - 1] != bytes[start] || close >= bytes.len() ||
+           bytes[close] != b')' {
+            return None;
+        }
+        Some((&css[start + 1..end - 1], close + 1))
+    } else {
+        find(bytes, start, b")").map(|close| (css[start..close].trim_right(), close + 1))
+    }
+}
+
+/// Split a url into the logical asset name it refers to and its query or fragment.
+///
+/// Returns `None` for urls that don't point into the asset store, such as data URIs
+/// or links to other hosts.
+fn resolve(name: &str, url: &str, base_url: &str) -> Option<(String, String)> {
+    let split = url.find(|c| c == '?' || c == '#').unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+
+    let (mut segments, relative): (Vec<&str>, &str) = if path.starts_with(base_url) {
+        (Vec::new(), &path[base_url.len()..])
+    } else if path.is_empty() || path.starts_with('/') || path.contains(':') {
+        return None;
+    } else {
+        let mut dir: Vec<&str> = name.split('/').collect();
+        dir.pop();
+        (dir, path)
+    };
+
+    for segment in relative.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                // Escaping the asset root can't name a registered file, so hand back
+                // the path as written and let the lookup fail on it.
+                if segments.pop().is_none() {
+                    return Some((String::from(path), String::from(suffix)));
+                }
+            }
+            s => segments.push(s),
+        }
+    }
+
+    Some((segments.join("/"), String::from(suffix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rewrite_urls;
+    use std::collections::HashMap;
+
+    fn assets() -> HashMap<String, String> {
+        let mut assets = HashMap::new();
+        assets.insert(String::from("img/a.png"), String::from("img/a-0123.png"));
+        assets.insert(String::from("css/other.css"), String::from("css/other-4567.css"));
+        assets.insert(String::from("fonts/a (1).woff"), String::from("fonts/a (1)-89ab.woff"));
+        assets
+    }
+
+    fn rewrite(css: &str) -> Result<String, Vec<String>> {
+        rewrite_urls("css/all.css", css, "/s/", &assets())
+    }
+
+    #[test]
+    fn rewrites_absolute_and_relative_urls() {
+        assert_eq!(rewrite("a { background: url(/s/img/a.png) }").unwrap(),
+                   "a { background: url(\"/s/img/a-0123.png\") }");
+        assert_eq!(rewrite("a { background: url( '../img/a.png?x#y' ) }").unwrap(),
+                   "a { background: url(\"/s/img/a-0123.png?x#y\") }");
+    }
+
+    #[test]
+    fn leaves_comments_alone() {
+        let css = "/* url(missing.png) */ a { color: red }";
+        assert_eq!(rewrite(css).unwrap(), css);
+    }
+
+    #[test]
+    fn leaves_other_strings_alone() {
+        let css = "a:after { content: \"url(missing.png)\" }";
+        assert_eq!(rewrite(css).unwrap(), css);
+    }
+
+    #[test]
+    fn handles_quoted_urls_containing_parens() {
+        assert_eq!(rewrite("@font-face { src: url(\"../fonts/a (1).woff\") }").unwrap(),
+                   "@font-face { src: url(\"/s/fonts/a (1)-89ab.woff\") }");
+    }
+
+    #[test]
+    fn rewrites_imports() {
+        assert_eq!(rewrite("@import url(other.css);").unwrap(),
+                   "@import url(\"/s/css/other-4567.css\");");
+        assert_eq!(rewrite("@import 'other.css';").unwrap(),
+                   "@import \"/s/css/other-4567.css\";");
+    }
+
+    #[test]
+    fn leaves_external_urls_alone() {
+        let css = "a { background: url(data:image/png;base64,AAAA) } \
+                   b { background: url(https://example.com/a.png) }";
+        assert_eq!(rewrite(css).unwrap(), css);
+    }
+
+    #[test]
+    fn reports_unknown_assets() {
+        let errors = rewrite("a { background: url(missing.png) } b { background: url(/s/x) }")
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("missing.png"));
+    }
+
+    #[test]
+    fn ignores_longer_identifiers() {
+        let css = "a { background: myurl(missing.png) }";
+        assert_eq!(rewrite(css).unwrap(), css);
+    }
+}
@@ -0,0 +1,81 @@
+//! Writing the asset store out to disk, for serving by a reverse proxy or CDN.
+
+use FileStorage;
+use compress;
+use state::LocalStorage;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Write every file in `store` under `dir`, at the same paths `lookup_file_with` serves
+/// them at, fingerprinted names included. Each file also gets a gzipped copy with a
+/// `.gz` suffix, for nginx's `gzip_static`. Files marked with `File::requires` are left
+/// out, since a proxy can't check their conditions.
+///
+/// The headers `lookup_file` would send with each file, such as its `Content-Type`, its
+/// `Etag` and those from the `HeaderPolicy`, go in a `_headers` file at the top of
+/// `dir`: each path on its own line, followed by its headers indented by two spaces.
+/// Netlify and Cloudflare Pages read this format directly, and it's easy to turn into
+/// `add_header` lines for nginx. Paths are relative to `dir`, so prefix them with the
+/// mount point if the tree is served under one.
+///
+/// Returns the number of files written, not counting the compressed copies.
+pub fn export_with<P>(store: &LocalStorage<FileStorage>, dir: P) -> io::Result<usize>
+    where P: AsRef<Path>
+{
+    let dir = dir.as_ref();
+    let mut count = 0;
+    let mut headers = String::new();
+
+    let files = &store.get().files;
+    let mut names: Vec<&String> = files.keys().collect();
+    names.sort();
+    for name in names {
+        if name.split('/').any(|s| s == ".." || s == ".") || name.starts_with('/') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("refusing to export {:?}", name)));
+        }
+
+        let file = files[name]();
+        if file.condition.is_some() {
+            continue;
+        }
+
+        let path = dir.join(name);
+        let file_name = match path.file_name() {
+            Some(f) => f.to_string_lossy().into_owned(),
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("{:?} has no file name", name)))
+            }
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::File::create(&path)?.write_all(file.bytes.as_ref())?;
+        fs::File::create(path.with_file_name(format!("{}.gz", file_name)))?
+            .write_all(compress::gzip(&file.bytes).as_slice())?;
+
+        headers.push_str(format!("/{}\n  Content-Type: {}\n  Etag: {}\n",
+                                 name,
+                                 file.mime,
+                                 file.etag)
+            .as_str());
+        for &(ref k, ref v) in &file.headers {
+            headers.push_str(format!("  {}: {}\n", k, v).as_str());
+        }
+        count += 1;
+    }
+
+    fs::File::create(dir.join("_headers"))?.write_all(headers.as_bytes())?;
+    Ok(count)
+}
+
+/// Like `export_with`, using the top-level `FILES` store.
+pub fn export<P>(dir: P) -> io::Result<usize>
+    where P: AsRef<Path>
+{
+    export_with(&::FILES, dir)
+}
@@ -9,6 +9,8 @@ use crypto::md5::Md5;
 use quote;
 use rocket::http::ContentType;
 use crypto::digest::Digest;
+use mime;
+use cache::Cache;
 
 #[derive(Debug)]
 pub enum ByteString {
@@ -58,6 +60,38 @@ pub struct StaticFile {
     pub bytes: ByteString,
     pub mime: ContentType,
     pub etag: String,
+    /// Extra headers to respond with, as chosen by a `HeaderPolicy`.
+    pub headers: Vec<(String, String)>,
+    /// The condition an `AccessGuard` must allow for this file to be served, if any.
+    pub condition: Option<String>,
+}
+
+impl StaticFile {
+    /// The name this file is served under in production, with a content hash
+    /// inserted before the extension: `img/otter.png` becomes
+    /// `img/otter-0123456789abcdef.png`.
+    pub fn fingerprint(&self) -> String {
+        let hash = digest(self.bytes.as_ref());
+        let base = self.name.rfind('/').map(|i| i + 1).unwrap_or(0);
+        match self.name[base..].rfind('.') {
+            Some(0) | None => format!("{}-{}", self.name, hash),
+            Some(dot) => {
+                let (stem, ext) = self.name.split_at(base + dot);
+                format!("{}-{}{}", stem, hash, ext)
+            }
+        }
+    }
+
+    /// Whether this file is a stylesheet whose `url(...)`s need rewriting.
+    pub fn is_css(&self) -> bool {
+        self.mime.ttype.to_string() == "text" && self.mime.subtype.to_string() == "css"
+    }
+
+    /// Replace the contents of this file, recomputing its etag.
+    pub fn set_bytes(&mut self, bytes: Vec<u8>) {
+        self.etag = etag(&bytes);
+        self.bytes = ByteString::Dynamic(bytes);
+    }
 }
 
 impl quote::ToTokens for StaticFile {
@@ -66,11 +100,27 @@ impl quote::ToTokens for StaticFile {
         StringTok(&self.name).to_tokens(tokens);
         tokens.append(", bytes: ");
         self.bytes.to_tokens(tokens);
-        tokens.append(format!(", mime: ::rocket::http::ContentType::new({:?}, {:?})",
-                              self.mime.ttype.to_string(),
-                              self.mime.subtype.to_string()));
+        tokens.append(format!(", mime: ::static_files::mime::content_type({:?})",
+                              self.mime.to_string()));
         tokens.append(", etag: ");
         StringTok(&self.etag).to_tokens(tokens);
+        tokens.append(", headers: vec![");
+        for &(ref k, ref v) in &self.headers {
+            tokens.append("(");
+            StringTok(k).to_tokens(tokens);
+            tokens.append(",");
+            StringTok(v).to_tokens(tokens);
+            tokens.append("),");
+        }
+        tokens.append("], condition: ");
+        match self.condition {
+            Some(ref c) => {
+                tokens.append("Some(");
+                StringTok(c).to_tokens(tokens);
+                tokens.append(")");
+            }
+            None => tokens.append("None"),
+        }
         tokens.append(" }");
     }
 }
@@ -80,6 +130,8 @@ impl quote::ToTokens for StaticFile {
 pub enum File {
     Plain(&'static str, &'static str),
     Sass(&'static str, &'static str, Vec<String>),
+    Typed(Box<File>, String),
+    Guarded(Box<File>, String),
 }
 
 /// Does no transformations.
@@ -99,29 +151,111 @@ pub fn sass_args<T>(a: &'static str, b: &'static str, args: Vec<T>) -> File
     File::Sass(a, b, args.into_iter().map(|x| x.into()).collect())
 }
 
+impl File {
+    /// Serve this file as `mime` rather than guessing from its name or contents.
+    /// Text types get `charset=utf-8` unless they specify a charset.
+    pub fn mime<S>(self, mime: S) -> File
+        where S: Into<String>
+    {
+        File::Typed(Box::new(self), mime.into())
+    }
+
+    /// Only serve this file to requests whose `AccessGuard` allows `condition`.
+    /// Lookups without a guard act as though the file doesn't exist.
+    pub fn requires<S>(self, condition: S) -> File
+        where S: Into<String>
+    {
+        File::Guarded(Box::new(self), condition.into())
+    }
+}
+
 /// # For internal use
 impl File {
     /// Used by the generated module.
     pub fn filename(self) -> String {
         use self::File::*;
-        String::from(match self {
-                         Plain(s, _) => s,
-                         Sass(s, _, _) => s,
-                     })
+        match self {
+            Plain(s, _) => String::from(s),
+            Sass(s, _, _) => String::from(s),
+            Typed(f, _) => f.filename(),
+            Guarded(f, _) => f.filename(),
+        }
+    }
+
+    /// Whether this file's type was given explicitly.
+    pub fn has_mime(&self) -> bool {
+        match self {
+            &File::Typed(..) => true,
+            &File::Guarded(ref f, _) => f.has_mime(),
+            _ => false,
+        }
+    }
+
+    /// The path this file is loaded from.
+    pub fn source(&self) -> &'static str {
+        match self {
+            &File::Plain(_, p) => p,
+            &File::Sass(_, p, _) => p,
+            &File::Typed(ref f, _) => f.source(),
+            &File::Guarded(ref f, _) => f.source(),
+        }
+    }
+
+    /// The name of the transformation applied to this file.
+    pub fn processor(&self) -> &'static str {
+        match self {
+            &File::Plain(..) => "plain",
+            &File::Sass(..) => "sass",
+            &File::Typed(ref f, _) => f.processor(),
+            &File::Guarded(ref f, _) => f.processor(),
+        }
     }
 
     /// Load the file from disk and apply transformations.
     ///
     /// You should never need to use this function, but it needs to be exported
-    /// in order for the generated module to use it.
-    pub fn resolve(self) -> StaticFile {
+    /// in order for the generated module to use it. `base_url` is what stylesheets
+    /// see as `$static_prefix`.
+    pub fn resolve(self, base_url: &str) -> StaticFile {
+        self.resolve_cached(base_url, None)
+    }
+
+    /// Like `resolve`, reusing earlier output from `cache` if the inputs to a
+    /// processor haven't changed.
+    pub fn resolve_cached(self, base_url: &str, cache: Option<&Cache>) -> StaticFile {
         match self {
             File::Plain(f, p) => Self::load_plain(String::from(f), p),
-            File::Sass(f, p, args) => Self::load_sass(String::from(f), p, args),
+            File::Sass(f, p, args) => {
+                let cache = match cache {
+                    Some(c) => c,
+                    None => return Self::load_sass(String::from(f), p, args, base_url),
+                };
+
+                let slot = Cache::slot(f, p);
+                let key = cache.sass_key(p, args.as_slice(), base_url);
+                match cache.get(slot.as_str(), key.as_str()) {
+                    Some(css) => Self::css_file(String::from(f), css),
+                    None => {
+                        let file = Self::load_sass(String::from(f), p, args, base_url);
+                        cache.put(slot.as_str(), key.as_str(), file.bytes.as_ref());
+                        file
+                    }
+                }
+            }
+            File::Typed(f, m) => {
+                let mut file = f.resolve_cached(base_url, cache);
+                file.mime = mime::content_type(mime::with_charset(m.as_str()).as_str());
+                file
+            }
+            File::Guarded(f, c) => {
+                let mut file = f.resolve_cached(base_url, cache);
+                file.condition = Some(c);
+                file
+            }
         }
     }
 
-    fn load_plain(name: String, pb: &'static str) -> StaticFile {
+    fn load_plain(name: String, pb: &str) -> StaticFile {
         #[cfg(not(debug_assertions))]
         println!("cargo:warning=Loading plain file: {:?}", pb);
         let mut s = Vec::new();
@@ -131,21 +265,23 @@ impl File {
             .read_to_end(&mut s)
             .expect(format!("{:?} couldn't be read", pb).as_str());
 
-        let n1 = name.clone();
+        let mime = mime::detect(name.as_str(), s.as_slice());
         let s1 = s.clone();
         StaticFile {
             name: name,
             bytes: ByteString::Dynamic(s),
-            mime: ContentType::from_extension(PathBuf::from(n1)
-                                                  .extension()
-                                                  .unwrap()
-                                                  .to_str()
-                                                  .unwrap()),
+            mime: mime::content_type(mime.as_str()),
             etag: Self::mk_etag(s1),
+            headers: Vec::new(),
+            condition: None,
         }
     }
 
-    fn load_sass(name: String, pb: &'static str, args: Vec<String>) -> StaticFile {
+    fn load_sass(name: String,
+                 pb: &str,
+                 args: Vec<String>,
+                 base_url: &str)
+                 -> StaticFile {
         #![allow(unused_mut)]
         println!("cargo:warning=Loading sass file: {:?}", pb);
 
@@ -158,7 +294,7 @@ impl File {
             .expect("failed to execute process");
 
         let mut contents = String::new();
-        contents.push_str("$static_prefix: '/s/';\n");
+        contents.push_str(format!("$static_prefix: '{}';\n", base_url).as_str());
         fs::File::open(pb)
             .expect("Couldn't open file")
             .read_to_string(&mut contents)
@@ -173,32 +309,61 @@ impl File {
         let output = child.wait_with_output().expect("Child didn't wait");
 
         if output.status.success() {
-            let o1 = output.stdout.clone();
-            StaticFile {
-                name: name,
-                bytes: ByteString::Dynamic(output.stdout),
-                mime: ContentType::CSS,
-                etag: Self::mk_etag(o1),
-            }
+            Self::css_file(name, output.stdout)
         } else {
             panic!("sass failed: {}",
                    String::from_utf8_lossy(output.stderr.as_slice()))
         }
     }
 
+    fn css_file(name: String, css: Vec<u8>) -> StaticFile {
+        let c1 = css.clone();
+        StaticFile {
+            name: name,
+            bytes: ByteString::Dynamic(css),
+            mime: mime::content_type(mime::with_charset("text/css").as_str()),
+            etag: Self::mk_etag(c1),
+            headers: Vec::new(),
+            condition: None,
+        }
+    }
+
     fn mk_etag<T>(s: T) -> String
         where T: AsRef<[u8]>
     {
-        let mut m = Md5::new();
-        m.input(s.as_ref());
-        let suffix: String = m.result_str()
-            .chars()
-            .take(16)
-            .collect();
-        format!("W/\"{}\"", suffix)
+        etag(s)
     }
 }
 
+/// The output of `sass --version`, trimmed.
+pub fn sass_version() -> String {
+    let output = Command::new("sass")
+        .arg("--version")
+        .output()
+        .expect("failed to execute process");
+    String::from(String::from_utf8_lossy(output.stdout.as_slice()).trim())
+}
+
+/// The etag static files with contents `s` are served with: a weak tag holding part of
+/// their MD5. Other responses can use it to tag their bodies the same way.
+pub fn etag<T>(s: T) -> String
+    where T: AsRef<[u8]>
+{
+    format!("W/\"{}\"", digest(s))
+}
+
+/// The first 16 hex digits of the MD5 of `s`.
+fn digest<T>(s: T) -> String
+    where T: AsRef<[u8]>
+{
+    let mut m = Md5::new();
+    m.input(s.as_ref());
+    m.result_str()
+        .chars()
+        .take(16)
+        .collect()
+}
+
 impl quote::ToTokens for File {
     fn to_tokens(&self, tokens: &mut Tokens) {
         match self {
@@ -219,6 +384,98 @@ impl quote::ToTokens for File {
                 f.to_tokens(tokens);
                 tokens.append(")");
             }
+            &File::Typed(ref f, ref m) => {
+                tokens.append("::static_files::file::File::Typed(Box::new(");
+                f.to_tokens(tokens);
+                tokens.append("), ");
+                StringTok(m).to_tokens(tokens);
+                tokens.append(")");
+            }
+            &File::Guarded(ref f, ref c) => {
+                tokens.append("::static_files::file::File::Guarded(Box::new(");
+                f.to_tokens(tokens);
+                tokens.append("), ");
+                StringTok(c).to_tokens(tokens);
+                tokens.append(")");
+            }
+        }
+    }
+}
+
+/// A directory served straight from disk in development, so that files can be added
+/// without touching the build script. Stylesheets are compiled on request: asking for
+/// `css/all.css` serves `css/all.scss` if there is no `css/all.css`.
+#[doc(hidden)]
+#[derive(Clone, Debug)]
+pub struct Dir {
+    pub prefix: String,
+    pub root: String,
+    pub sass_args: Vec<String>,
+    /// Types from `Config::mime_type`, which take precedence over `mime::detect`.
+    pub mime_types: Vec<(String, String)>,
+}
+
+impl Dir {
+    /// Load the file that would be registered as `name`, if it's in this directory.
+    ///
+    /// `name` should already be normalized, as `lookup_file_with` does.
+    pub fn load(&self, name: &str, base_url: &str) -> Option<StaticFile> {
+        let prefix = self.prefix.trim_matches('/');
+        let rest = if prefix.is_empty() {
+            name
+        } else if name.starts_with(format!("{}/", prefix).as_str()) {
+            &name[prefix.len() + 1..]
+        } else {
+            return None;
+        };
+
+        let path = PathBuf::from(&self.root).join(rest);
+        if path.is_file() {
+            return Some(self.typed(File::load_plain(String::from(name),
+                                                    &path.to_string_lossy())));
+        }
+
+        let is_partial = path.file_name().map_or(true, |f| f.to_string_lossy().starts_with('_'));
+        if !rest.ends_with(".css") || is_partial {
+            return None;
+        }
+        let scss = path.with_extension("scss");
+        if scss.is_file() {
+            Some(self.typed(File::load_sass(String::from(name),
+                                            &scss.to_string_lossy(),
+                                            self.sass_args.clone(),
+                                            base_url)))
+        } else {
+            None
+        }
+    }
+
+    /// Give `file` the type configured for its extension, if there is one.
+    fn typed(&self, mut file: StaticFile) -> StaticFile {
+        if let Some(m) = mime::from_table(&self.mime_types, &file.name) {
+            file.mime = mime::content_type(mime::with_charset(m).as_str());
+        }
+        file
+    }
+}
+
+impl quote::ToTokens for Dir {
+    fn to_tokens(&self, tokens: &mut Tokens) {
+        let arg_strings: Vec<StringTok> = self.sass_args.iter().map(|x| StringTok(x)).collect();
+        tokens.append("::static_files::file::Dir { prefix: ");
+        StringTok(&self.prefix).to_tokens(tokens);
+        tokens.append(", root: ");
+        StringTok(&self.root).to_tokens(tokens);
+        tokens.append(", sass_args: vec!");
+        arg_strings.to_tokens(tokens);
+        tokens.append(", mime_types: vec![");
+        for &(ref ext, ref mime) in &self.mime_types {
+            tokens.append("(String::from(");
+            StringTok(ext).to_tokens(tokens);
+            tokens.append("), String::from(");
+            StringTok(mime).to_tokens(tokens);
+            tokens.append(")),");
         }
+        tokens.append("] }");
     }
 }
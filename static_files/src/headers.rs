@@ -0,0 +1,107 @@
+//! Extra response headers for static files, chosen by content type.
+
+use file::StaticFile;
+use quote;
+use quote::Tokens;
+use rocket::http::ContentType;
+
+/// Which security headers static responses carry.
+#[derive(Clone, Debug)]
+pub struct HeaderPolicy {
+    /// Send `X-Content-Type-Options: nosniff` with every file.
+    pub nosniff: bool,
+    /// `Content-Security-Policy` for HTML and SVG files, which can run scripts.
+    pub content_security_policy: Option<String>,
+    /// `Access-Control-Allow-Origin` for fonts, which browsers won't load from another
+    /// origin without it.
+    pub font_cors_origin: Option<String>,
+    /// `Cross-Origin-Resource-Policy` for fonts.
+    pub font_resource_policy: Option<String>,
+    /// `Cache-Control` for every file.
+    pub cache_control: Option<String>,
+}
+
+impl HeaderPolicy {
+    /// `nosniff` everywhere, and fonts loadable from any origin.
+    pub fn new() -> HeaderPolicy {
+        HeaderPolicy {
+            nosniff: true,
+            content_security_policy: None,
+            font_cors_origin: Some(String::from("*")),
+            font_resource_policy: Some(String::from("cross-origin")),
+            cache_control: None,
+        }
+    }
+
+    /// The headers to send with a file of type `mime`.
+    pub fn headers_for(&self, mime: &ContentType) -> Vec<(String, String)> {
+        let ttype = mime.ttype.to_string().to_lowercase();
+        let subtype = mime.subtype.to_string().to_lowercase();
+        let mut headers = Vec::new();
+
+        if self.nosniff {
+            headers.push((String::from("X-Content-Type-Options"), String::from("nosniff")));
+        }
+
+        if let Some(ref cc) = self.cache_control {
+            headers.push((String::from("Cache-Control"), cc.clone()));
+        }
+
+        let is_document = (ttype == "text" && subtype == "html") ||
+                          (ttype == "image" && subtype == "svg+xml");
+        if let (true, &Some(ref csp)) = (is_document, &self.content_security_policy) {
+            headers.push((String::from("Content-Security-Policy"), csp.clone()));
+        }
+
+        let is_font = ttype == "font" || subtype == "vnd.ms-fontobject" ||
+                      subtype.starts_with("font-") || subtype.starts_with("x-font-");
+        if is_font {
+            if let Some(ref origin) = self.font_cors_origin {
+                headers.push((String::from("Access-Control-Allow-Origin"), origin.clone()));
+                if origin != "*" {
+                    headers.push((String::from("Vary"), String::from("Origin")));
+                }
+            }
+            if let Some(ref corp) = self.font_resource_policy {
+                headers.push((String::from("Cross-Origin-Resource-Policy"), corp.clone()));
+            }
+        }
+
+        headers
+    }
+
+    /// Set the headers `file` is served with according to this policy.
+    pub fn apply(&self, mut file: StaticFile) -> StaticFile {
+        file.headers = self.headers_for(&file.mime);
+        file
+    }
+}
+
+impl Default for HeaderPolicy {
+    fn default() -> HeaderPolicy {
+        HeaderPolicy::new()
+    }
+}
+
+fn option_tokens(o: &Option<String>, tokens: &mut Tokens) {
+    match o {
+        &Some(ref s) => tokens.append(format!("Some(String::from({:?}))", s)),
+        &None => tokens.append("None"),
+    }
+}
+
+impl quote::ToTokens for HeaderPolicy {
+    fn to_tokens(&self, tokens: &mut Tokens) {
+        tokens.append(format!("::static_files::headers::HeaderPolicy {{ nosniff: {:?}",
+                              self.nosniff));
+        tokens.append(", content_security_policy: ");
+        option_tokens(&self.content_security_policy, tokens);
+        tokens.append(", font_cors_origin: ");
+        option_tokens(&self.font_cors_origin, tokens);
+        tokens.append(", font_resource_policy: ");
+        option_tokens(&self.font_resource_policy, tokens);
+        tokens.append(", cache_control: ");
+        option_tokens(&self.cache_control, tokens);
+        tokens.append(" }");
+    }
+}
@@ -50,38 +50,144 @@
 //!
 //! In whatever route you want, use this code:
 //!
-//!     #[get("/static/<path..>")]
+//!     #[get("/<path..>")]
 //!     fn get(path: PathBuf, inm: Option<IfNoneMatch>) -> Option<Cached<StaticResponse>> {
 //!         web::lookup_file(path, inm)
 //!     }
 //!
+//! Rocket answers HEAD requests with the same route, sending the headers, including
+//! `Content-Length`, without the body.
+//!
+//! `web::lookup_file_with_options` can also serve an index file for directory paths,
+//! and a fallback file for paths that match nothing, for hosting single-page apps.
+//!
+//! Files registered with `plain(...).requires("logged_in")` are only served through
+//! `web::lookup_file_guarded`, and only when its `AccessGuard` allows the condition.
+//!
+//! Mount these at `my_files::MOUNT_POINT`, which is `/s` unless configured otherwise
+//! with `load_files_with_config`. Templates should link to assets with
+//! `my_files::asset_url("css/all.css")`.
+//!
 //! Note that you do need to call `load_files` first; otherwise, this code will complain
 //! that the FILES variable hasn't been initialized.
+//!
+//! # Development directories
+//!
+//! `Config::dev_dir` serves a whole directory from disk in development, compiling
+//! `.scss` files when the matching `.css` name is requested, so new files can be used
+//! without editing the build script. It has no effect in production, where only files
+//! passed to `load_files` are served.
+//!
+//! # Content types
+//!
+//! Files are served with the type their extension maps to in `mime`, or failing that
+//! one guessed from their contents. `Config::mime_type` adds or replaces extensions,
+//! and `File::mime` sets the type of a single file. Text types are served with
+//! `charset=utf-8`.
+//!
+//! # Headers
+//!
+//! Every file is served with `X-Content-Type-Options: nosniff`, and fonts with CORS and
+//! `Cross-Origin-Resource-Policy` headers that let pages on other origins use them. A
+//! `Content-Security-Policy` for HTML and SVG files can be set with `Config::headers`.
+//!
+//! # Fingerprinting
+//!
+//! In production, every file is also registered under a name containing a hash of its
+//! contents, such as `img/otter-0123456789abcdef.png`. Stylesheets have their `url(...)`
+//! and `@import` references rewritten to these names, so they can be cached forever,
+//! including references to other stylesheets. A stylesheet that refers to a file not
+//! passed to `load_files` fails the build.
+//!
+//! # Exporting
+//!
+//! `export::export(dir)` writes every file in `FILES` to `dir`, along with gzipped
+//! copies and a `_headers` file listing the headers each is served with, so a reverse
+//! proxy can serve them from disk instead.
+//!
+//! # Size budgets
+//!
+//! `Config::budget` and `Config::total_budget` set limits on the size of processed
+//! files, optionally gzipped. Production builds that exceed them fail with a table of
+//! the offending files.
+//!
+//! # Caching
+//!
+//! Production builds keep sass output in `$OUT_DIR/asset-cache`, or the directory
+//! given to `Config::cache_dir`, keyed on the stylesheets, their load paths, the
+//! arguments and the sass version. Stylesheets whose inputs haven't changed aren't
+//! compiled again.
+//!
+//! # Manifest
+//!
+//! Production builds also write `manifest.json` (or `<bundle>-manifest.json` for named
+//! bundles) to $OUT_DIR, and to the path given to `Config::manifest` if any. It lists
+//! every file's name, source path, processor, MIME type, size, etag and fingerprinted
+//! name.
 
 #![feature(const_fn)]
-#![recursion_limit = "128"]
+#![recursion_limit = "256"]
 
 extern crate state;
 extern crate rocket;
 extern crate crypto;
+extern crate flate2;
+extern crate rustc_serialize;
 
 #[macro_use]
 extern crate quote;
 
+use crypto::digest::Digest;
+use crypto::md5::Md5;
 use quote::Tokens;
 use state::LocalStorage;
 use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::path::PathBuf;
 
+pub mod budget;
+pub mod cache;
+pub mod compress;
+pub mod config;
+pub mod css;
+pub mod export;
 pub mod file;
+pub mod headers;
+pub mod manifest;
+pub mod mime;
 pub mod web;
+pub use config::Config;
 pub use web::*;
 
 use file::StringTok;
 use file::StaticFile;
 
-pub type FileStorage = HashMap<String, Box<Fn() -> StaticFile + Send>>;
+pub struct FileStorage {
+    /// Loaders for each registered file, by name.
+    pub files: HashMap<String, Box<Fn() -> StaticFile + Send>>,
+    /// Loaders for directories served from disk in development, which are tried in
+    /// order for names not in `files`.
+    pub dirs: Vec<Box<Fn(&str) -> Option<StaticFile> + Send>>,
+}
+
+impl FileStorage {
+    pub fn new() -> FileStorage {
+        FileStorage {
+            files: HashMap::new(),
+            dirs: Vec::new(),
+        }
+    }
+
+    /// Load the file registered as `name`, or found under that name in a development
+    /// directory.
+    pub fn load(&self, name: &str) -> Option<StaticFile> {
+        match self.files.get(name) {
+            Some(loader) => Some(loader()),
+            None => self.dirs.iter().filter_map(|d| d(name)).next(),
+        }
+    }
+}
 
 /// Top-level static storage of a map of filepaths to files.
 ///
@@ -92,51 +198,268 @@ pub type FileStorage = HashMap<String, Box<Fn() -> StaticFile + Send>>;
 /// which is stored in the binary's __DATA section.
 pub static FILES: LocalStorage<FileStorage> = LocalStorage::new();
 
+/// An MD5 over every name and its contents, in order.
+fn bundle_digest<'a, I>(parts: I) -> String
+    where I: Iterator<Item = (String, &'a [u8])>
+{
+    let mut m = Md5::new();
+    for (name, bytes) in parts {
+        m.input(format!("{}:{}:", name.len(), name).as_bytes());
+        m.input(format!("{}:", bytes.len()).as_bytes());
+        m.input(bytes);
+    }
+    m.result_str()
+}
+
 /// This highly magical function loads all the `File`s given in the input
 /// and places them in the top-level `FILES` static hashmap.
 pub fn load_files(v: Vec<file::File>) {
-    let out = ::std::env::var("OUT_DIR").unwrap();
-    let mut f = fs::File::create(out + "/generated.rs").expect("File not created");
+    load_files_with_config(v, Config::new())
+}
+
+/// Like `load_files`, but with a custom mount point or CDN.
+///
+/// Besides `load_files`, the generated module exports `MOUNT_POINT`, the path to mount
+/// the static routes at; `STATIC_URL`, the URL assets are referred to by;
+/// `asset_url(name)`, which gives the URL of the asset registered as `name`;
+/// `store()` and `lookup_file`, for serving from the store it fills; and
+/// `BUNDLE_DIGEST`, which changes whenever any file's name or contents do. In
+/// development, where files are loaded on request, it covers names and source paths.
+///
+/// Files are sorted by name, so the module is byte-for-byte the same for the same
+/// inputs. `Config::sass_version` makes that hold across machines too.
+///
+/// With `Config::bundle`, the module is written to `<name>.rs` instead of
+/// `generated.rs`, and fills its own `FILES` rather than the top-level one, so that
+/// several bundles with different mount points and headers can be served side by side.
+pub fn load_files_with_config(v: Vec<file::File>, config: Config) {
+    let out = PathBuf::from(::std::env::var("OUT_DIR").unwrap());
+    let module = config.bundle_name().unwrap_or("generated");
+    let path = out.join(format!("{}.rs", module));
     let mut loaders = Tokens::new();
+    #[allow(unused_mut)]
+    let mut items = Tokens::new();
+    #[allow(unused_mut)]
+    let mut urls = Tokens::new();
+
+    let mount_point = config.mount_point();
+    let base_url = config.base_url();
+    let mut policy = config.header_policy().clone();
+    if let Some(cc) = config.cache_control_value() {
+        policy.cache_control = Some(String::from(cc));
+    }
+
+    let mut v: Vec<file::File> = v.into_iter()
+        .map(|f| match config.mime_for(f.clone().filename().as_str()) {
+            Some(m) if !f.has_mime() => f.mime(m),
+            _ => f,
+        })
+        .collect();
+
+    // Sorting keeps the generated module the same however the build script orders
+    // its files.
+    v.sort_by_key(|f| f.clone().filename());
+    for pair in v.windows(2) {
+        let (a, b) = (pair[0].clone().filename(), pair[1].clone().filename());
+        if a == b {
+            panic!("{:?} is registered more than once", a);
+        }
+    }
+
+    if let Some(version) = config.required_sass_version() {
+        let actual = file::sass_version();
+        if !actual.contains(version) {
+            panic!("sass {} is required, but `sass --version` says {:?}", version, actual);
+        }
+    }
+
+    let digest: String;
 
     #[cfg(debug_assertions)]
     {
+        digest = bundle_digest(v.iter()
+            .map(|f| (f.clone().filename(), f.source().as_bytes())));
+
         for static_file in v {
             let fname = static_file.clone().filename();
             let name = StringTok(&fname);
             loaders.append(quote! {
-                m.insert(#name, Box::new(|| #static_file.resolve()));
+                m.files.insert(#name, Box::new(|| header_policy().apply(#static_file.resolve(STATIC_URL))));
+            })
+        }
+
+        for dir in config.dev_dirs() {
+            loaders.append(quote! {
+                m.dirs.push(Box::new(|name: &str| {
+                    #dir.load(name, STATIC_URL).map(|f| header_policy().apply(f))
+                }));
             })
         }
     }
 
     #[cfg(not(debug_assertions))]
     {
-        for static_file in v {
-            let file = static_file.resolve();
+        let cache = cache::Cache::new(match config.cache_path() {
+            Some(p) => p.to_path_buf(),
+            None => out.join("asset-cache"),
+        });
+
+        let sources = v.clone();
+        let mut files: Vec<StaticFile> = v.into_iter()
+            .map(|f| f.resolve_cached(base_url.as_str(), Some(&cache)))
+            .collect();
+
+        let mut fingerprints: HashMap<String, String> = files.iter()
+            .filter(|f| !f.is_css())
+            .map(|f| (f.name.clone(), f.fingerprint()))
+            .collect();
+
+        // A stylesheet's fingerprint depends on the names it refers to, so stylesheets
+        // are rewritten once everything they refer to has been, including each other.
+        let mut pending: Vec<usize> = (0..files.len()).filter(|&i| files[i].is_css()).collect();
+        while !pending.is_empty() {
+            let mut errors = Vec::new();
+            let mut still_pending = Vec::new();
+            for i in pending.iter().cloned() {
+                let rewritten = css::rewrite_urls(files[i].name.as_str(),
+                                                  String::from_utf8_lossy(files[i].bytes.as_ref())
+                                                      .as_ref(),
+                                                  base_url.as_str(),
+                                                  &fingerprints);
+                match rewritten {
+                    Ok(c) => {
+                        files[i].set_bytes(c.into_bytes());
+                        fingerprints.insert(files[i].name.clone(), files[i].fingerprint());
+                    }
+                    Err(mut e) => {
+                        errors.append(&mut e);
+                        still_pending.push(i);
+                    }
+                }
+            }
+            if still_pending.len() == pending.len() {
+                panic!("unresolved asset references (or stylesheets that import each \
+                        other):\n{}",
+                       errors.join("\n"));
+            }
+            pending = still_pending;
+        }
+
+        if let Err(table) = config.budgets().check(&files) {
+            panic!("{}", table);
+        }
+
+        let files: Vec<StaticFile> = files.into_iter().map(|f| policy.apply(f)).collect();
+
+        digest = bundle_digest(files.iter().map(|f| (f.name.clone(), f.bytes.as_ref())));
+
+        let manifest = manifest::Manifest {
+            static_url: base_url.clone(),
+            digest: digest.clone(),
+            files: sources.iter()
+                .zip(files.iter())
+                .map(|(source, file)| manifest::Entry::new(source, file))
+                .collect(),
+        };
+        manifest.write_to(out.join(match config.bundle_name() {
+            Some(b) => format!("{}-manifest.json", b),
+            None => String::from("manifest.json"),
+        }));
+        if let Some(p) = config.manifest_path() {
+            manifest.write_to(p);
+        }
+
+        for (i, file) in files.into_iter().enumerate() {
             let fname = file.name.clone();
+            let fingerprint = file.fingerprint();
             let name = StringTok(&fname);
+            let fp_name = StringTok(&fingerprint);
+            let loader = quote::Ident::new(format!("__file_{}", i));
+            items.append(quote! {
+                fn #loader() -> ::static_files::file::StaticFile { #file }
+            });
             loaders.append(quote! {
-                m.insert(#name, Box::new(|| #file));
+                m.files.insert(#name, Box::new(#loader));
+                m.files.insert(#fp_name, Box::new(#loader));
+            });
+            let (logical, target) = (fname.as_str(), fingerprint.as_str());
+            urls.append(quote! {
+                #logical => #target,
             });
         }
     }
 
+    let store = match config.bundle_name() {
+        Some(_) => quote! {
+            pub static FILES: ::state::LocalStorage<::static_files::FileStorage> =
+                ::state::LocalStorage::new();
+
+            pub fn store() -> &'static ::state::LocalStorage<::static_files::FileStorage> {
+                &FILES
+            }
+        },
+        None => quote! {
+            pub fn store() -> &'static ::state::LocalStorage<::static_files::FileStorage> {
+                &::static_files::FILES
+            }
+        },
+    };
+
+    let base = base_url.as_str();
+    let digest = digest.as_str();
     let tokens = quote! {
-      use std::collections::HashMap;
+      #store
+
+      pub const BUNDLE_DIGEST: &'static str = #digest;
+
+      pub const MOUNT_POINT: &'static str = #mount_point;
+
+      pub const STATIC_URL: &'static str = #base;
+
+      pub fn asset_url(name: &str) -> String {
+        let target = match name {
+          #urls
+          _ => name,
+        };
+        format!("{}{}", STATIC_URL, target)
+      }
+
+      #[allow(dead_code)]
+      fn header_policy() -> ::static_files::headers::HeaderPolicy {
+        #policy
+      }
+
+      #items
 
       pub fn load_files_with(store: &::state::LocalStorage<::static_files::FileStorage>) {
         store.set(|| {
-          let mut m: ::static_files::FileStorage = HashMap::new();
+          let mut m = ::static_files::FileStorage::new();
           #loaders
           m
         });
       }
 
       pub fn load_files() {
-        load_files_with(&::static_files::FILES);
+        load_files_with(store());
+      }
+
+      #[allow(dead_code)]
+      pub fn lookup_file(path: ::std::path::PathBuf,
+                         inm: Option<::static_files::IfNoneMatch>)
+                         -> Option<::static_files::Cached<::static_files::StaticResponse>> {
+        ::static_files::lookup_file_with(store(), path, inm)
       }
     };
 
-    f.write_all(tokens.to_string().as_bytes()).expect("Didn't write to file")
+    // Leaving an identical module alone keeps its mtime, so that nothing downstream
+    // sees a change.
+    let generated = tokens.to_string();
+    let mut existing = String::new();
+    if let Ok(mut old) = fs::File::open(&path) {
+        let _ = old.read_to_string(&mut existing);
+    }
+    if existing != generated {
+        let mut f = fs::File::create(&path).expect("File not created");
+        f.write_all(generated.as_bytes()).expect("Didn't write to file")
+    }
 }
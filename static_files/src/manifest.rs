@@ -0,0 +1,61 @@
+//! A JSON description of the embedded assets, for tools outside the binary.
+
+use file::File;
+use file::StaticFile;
+use rustc_serialize::json;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(RustcEncodable)]
+pub struct Manifest {
+    pub static_url: String,
+    /// A digest of every file's name and contents, which changes if any of them do.
+    pub digest: String,
+    pub files: Vec<Entry>,
+}
+
+#[derive(RustcEncodable)]
+pub struct Entry {
+    /// The name the file is registered under, e.g. `css/all.css`.
+    pub name: String,
+    /// The path the file was loaded from.
+    pub source: String,
+    /// How the file was processed: `plain` or `sass`.
+    pub processor: String,
+    pub mime: String,
+    /// Size in bytes of the processed file.
+    pub size: usize,
+    pub etag: String,
+    /// The name the file is also served under, containing a hash of its contents.
+    pub fingerprint: String,
+    /// The condition set with `File::requires`, if any.
+    pub condition: Option<String>,
+}
+
+impl Entry {
+    pub fn new(source: &File, file: &StaticFile) -> Entry {
+        Entry {
+            name: file.name.clone(),
+            source: String::from(source.source()),
+            processor: String::from(source.processor()),
+            mime: file.mime.to_string(),
+            size: file.bytes.as_ref().len(),
+            etag: file.etag.clone(),
+            fingerprint: file.fingerprint(),
+            condition: file.condition.clone(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn write_to<P>(&self, path: P)
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let mut f = fs::File::create(path)
+            .expect(format!("{:?} couldn't be created", path).as_str());
+        write!(f, "{}\n", json::as_pretty_json(self))
+            .expect(format!("{:?} couldn't be written", path).as_str());
+    }
+}
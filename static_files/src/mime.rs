@@ -0,0 +1,185 @@
+//! MIME type detection for static files.
+//!
+//! Types are looked up by extension first. Files whose extension isn't known, or that
+//! have none, have their contents sniffed. Text types get a `charset=utf-8` parameter.
+
+use rocket::http::ContentType;
+use std::cmp;
+
+/// Extensions the browser needs to see the right type for, including some that
+/// `ContentType::from_extension` doesn't know about.
+const TYPES: &'static [(&'static str, &'static str)] = &[("css", "text/css"),
+                                                         ("csv", "text/csv"),
+                                                         ("htm", "text/html"),
+                                                         ("html", "text/html"),
+                                                         ("md", "text/markdown"),
+                                                         ("txt", "text/plain"),
+                                                         ("js", "application/javascript"),
+                                                         ("json", "application/json"),
+                                                         ("map", "application/json"),
+                                                         ("webmanifest",
+                                                          "application/manifest+json"),
+                                                         ("xml", "application/xml"),
+                                                         ("pdf", "application/pdf"),
+                                                         ("wasm", "application/wasm"),
+                                                         ("bmp", "image/bmp"),
+                                                         ("gif", "image/gif"),
+                                                         ("ico", "image/x-icon"),
+                                                         ("jpeg", "image/jpeg"),
+                                                         ("jpg", "image/jpeg"),
+                                                         ("png", "image/png"),
+                                                         ("svg", "image/svg+xml"),
+                                                         ("webp", "image/webp"),
+                                                         ("eot",
+                                                          "application/vnd.ms-fontobject"),
+                                                         ("otf", "font/otf"),
+                                                         ("ttf", "font/ttf"),
+                                                         ("woff", "font/woff"),
+                                                         ("woff2", "font/woff2")];
+
+/// Types that are text even though they aren't `text/*`.
+const TEXT_TYPES: &'static [&'static str] = &["application/javascript",
+                                              "application/json",
+                                              "application/manifest+json",
+                                              "application/xml",
+                                              "image/svg+xml"];
+
+/// The lowercased extension of a file named `name`, without the dot.
+pub fn extension(name: &str) -> Option<String> {
+    let file = name.rsplit('/').next().unwrap_or(name);
+    file.rfind('.').map(|i| file[i + 1..].to_lowercase())
+}
+
+/// The type `types`, a list of `(extension, type)` pairs like those given to
+/// `Config::mime_type`, assigns a file named `name`. Later pairs take precedence.
+pub fn from_table<'a>(types: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    extension(name)
+        .and_then(|ext| types.iter().rev().find(|&&(ref e, _)| *e == ext))
+        .map(|&(_, ref m)| m.as_str())
+}
+
+/// The type for a file named `name`, judging by its extension.
+pub fn from_name(name: &str) -> Option<&'static str> {
+    extension(name).and_then(|ext| TYPES.iter().find(|&&(e, _)| e == ext).map(|&(_, t)| t))
+}
+
+/// Guess a type from the first few bytes of a file.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    const MAGIC: &'static [(&'static [u8], &'static str)] = &[(b"\x89PNG\r\n\x1a\n",
+                                                               "image/png"),
+                                                              (b"GIF87a", "image/gif"),
+                                                              (b"GIF89a", "image/gif"),
+                                                              (b"\xff\xd8\xff", "image/jpeg"),
+                                                              (b"\x00\x00\x01\x00",
+                                                               "image/x-icon"),
+                                                              (b"%PDF-", "application/pdf"),
+                                                              (b"wOFF", "font/woff"),
+                                                              (b"wOF2", "font/woff2"),
+                                                              (b"OTTO", "font/otf"),
+                                                              (b"\x00\x01\x00\x00",
+                                                               "font/ttf")];
+
+    if let Some(&(_, t)) = MAGIC.iter().find(|&&(m, _)| bytes.starts_with(m)) {
+        return Some(t);
+    }
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    let head = String::from_utf8_lossy(&bytes[..cmp::min(bytes.len(), 512)]).to_lowercase();
+    let head = head.trim_left();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        Some("text/html")
+    } else if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+        Some("image/svg+xml")
+    } else if head.starts_with("<?xml") {
+        Some("application/xml")
+    } else if ::std::str::from_utf8(bytes).is_ok() && !bytes.contains(&0) {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+/// Add `charset=utf-8` to text types that don't already specify a charset.
+pub fn with_charset(mime: &str) -> String {
+    let essence = mime.split(';').next().unwrap_or(mime).trim();
+    let is_text = essence.starts_with("text/") || TEXT_TYPES.contains(&essence);
+    if is_text && !mime.contains("charset=") {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        String::from(mime)
+    }
+}
+
+/// The type to serve `bytes`, registered as `name`, with.
+pub fn detect(name: &str, bytes: &[u8]) -> String {
+    with_charset(from_name(name).or_else(|| sniff(bytes)).unwrap_or("application/octet-stream"))
+}
+
+/// Parse a type as produced by `detect`.
+pub fn content_type(mime: &str) -> ContentType {
+    mime.parse().expect(format!("invalid MIME type {:?}", mime).as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, extension, from_name, from_table, sniff, with_charset};
+
+    #[test]
+    fn extensions_come_from_the_last_segment() {
+        assert_eq!(extension("css/All.CSS"), Some(String::from("css")));
+        assert_eq!(extension("Makefile"), None);
+        assert_eq!(extension("a.b/c"), None);
+    }
+
+    #[test]
+    fn known_extensions_have_types() {
+        assert_eq!(from_name("fonts/fontawesome-webfont.woff2"), Some("font/woff2"));
+        assert_eq!(from_name("site.webmanifest"), Some("application/manifest+json"));
+        assert_eq!(from_name("img/logo.SVG"), Some("image/svg+xml"));
+        assert_eq!(from_name("notes.unknown"), None);
+    }
+
+    #[test]
+    fn later_table_entries_win() {
+        let types = vec![(String::from("md"), String::from("text/plain")),
+                         (String::from("md"), String::from("text/x-markdown"))];
+        assert_eq!(from_table(&types, "docs/README.md"), Some("text/x-markdown"));
+        assert_eq!(from_table(&types, "README"), None);
+    }
+
+    #[test]
+    fn sniffs_magic_numbers() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"wOF2\x00\x01\x00\x00"), Some("font/woff2"));
+        assert_eq!(sniff(b"\xff\xfe\x00\x01"), None);
+    }
+
+    #[test]
+    fn sniffs_html() {
+        assert_eq!(sniff(b"\n  <!DOCTYPE html>\n<html>"), Some("text/html"));
+        assert_eq!(sniff(b"<HTML><body></body></HTML>"), Some("text/html"));
+        assert_eq!(sniff(b"just some text"), Some("text/plain"));
+    }
+
+    #[test]
+    fn text_types_get_a_charset() {
+        assert_eq!(with_charset("text/css"), "text/css; charset=utf-8");
+        assert_eq!(with_charset("image/svg+xml"), "image/svg+xml; charset=utf-8");
+        assert_eq!(with_charset("image/png"), "image/png");
+    }
+
+    #[test]
+    fn explicit_charsets_are_kept() {
+        assert_eq!(with_charset("text/html; charset=iso-8859-1"),
+                   "text/html; charset=iso-8859-1");
+    }
+
+    #[test]
+    fn detect_prefers_the_extension() {
+        assert_eq!(detect("a.css", b"<!doctype html>"), "text/css; charset=utf-8");
+        assert_eq!(detect("favicon", b"\x00\x00\x01\x00"), "image/x-icon");
+        assert_eq!(detect("blob", b"\x00\xff"), "application/octet-stream");
+    }
+}
@@ -6,7 +6,7 @@ use rocket::response::Responder;
 use rocket::request::FromRequest;
 use rocket::request::Request;
 use rocket::outcome::Outcome;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use rocket::response::Response;
 use rocket::http::Status;
 use std::io::Cursor;
@@ -15,10 +15,13 @@ pub struct StaticResponse(pub StaticFile);
 
 impl<'r> Responder<'r> for StaticResponse {
     fn respond(self) -> Result<Response<'r>, Status> {
-        Response::build()
-            .header(self.0.mime)
-            .raw_header("Etag", self.0.etag)
-            .sized_body(Cursor::new(self.0.bytes))
+        let mut response = Response::build();
+        response.header(self.0.mime)
+            .raw_header("Etag", self.0.etag);
+        for (k, v) in self.0.headers {
+            response.raw_header(k, v);
+        }
+        response.sized_body(Cursor::new(self.0.bytes))
             .ok()
     }
 }
@@ -65,15 +68,201 @@ pub fn lookup_file_with(store: &LocalStorage<FileStorage>,
                         path: PathBuf,
                         inm: Option<IfNoneMatch>)
                         -> Option<Cached<StaticResponse>> {
-    store.get().get(&String::from(path.to_str().unwrap())).map(|x| {
-        let sf = x();
+    lookup_file_with_options(store, path, inm, &LookupOptions::default())
+}
+
+/// How request paths are matched against the store.
+#[derive(Clone, Debug, Default)]
+pub struct LookupOptions {
+    /// The file to serve for a path that names a directory, such as `index.html`.
+    pub index: Option<String>,
+    /// The file to serve for paths that match nothing else.
+    pub fallback: Option<Fallback>,
+}
+
+/// Serve `file` for any unmatched path under `prefix`, as a single-page app that does
+/// its own routing would want.
+#[derive(Clone, Debug)]
+pub struct Fallback {
+    /// Only paths under this directory fall back, e.g. `docs`. Empty matches everything.
+    pub prefix: String,
+    /// The name of the file to serve, e.g. `docs/index.html`.
+    pub file: String,
+}
+
+impl Fallback {
+    fn matches(&self, name: &str) -> bool {
+        let prefix = self.prefix.trim_matches('/');
+        prefix.is_empty() || name == prefix || name.starts_with(format!("{}/", prefix).as_str())
+    }
+}
+
+/// Decides whether a request may see files marked with `File::requires`.
+///
+/// Implement this for a request guard, such as one for the logged-in user, and pass it
+/// to `lookup_file_guarded`.
+pub trait AccessGuard {
+    fn allows(&self, condition: &str) -> bool;
+}
+
+/// Allows nothing, so only unmarked files are served.
+pub struct Public;
+
+impl AccessGuard for Public {
+    fn allows(&self, _: &str) -> bool {
+        false
+    }
+}
+
+/// Like `lookup_file_with`, with control over how paths are matched.
+///
+/// Paths containing `..`, non-UTF-8 segments or characters that a decoded URL
+/// shouldn't contain are treated as missing.
+///
+/// Fallback responses are sent with `Cache-Control: no-cache`, so that clients
+/// revalidate them against the fallback file's etag rather than caching them under
+/// every path they were requested at.
+pub fn lookup_file_with_options(store: &LocalStorage<FileStorage>,
+                                path: PathBuf,
+                                inm: Option<IfNoneMatch>,
+                                options: &LookupOptions)
+                                -> Option<Cached<StaticResponse>> {
+    lookup_file_guarded(store, path, inm, options, &Public)
+}
+
+/// Like `lookup_file_with_options`, also serving files marked with `File::requires`
+/// if `guard` allows them. Files it doesn't allow are treated as missing, and files it
+/// does are sent with `Cache-Control: private`.
+pub fn lookup_file_guarded<G>(store: &LocalStorage<FileStorage>,
+                              path: PathBuf,
+                              inm: Option<IfNoneMatch>,
+                              options: &LookupOptions,
+                              guard: &G)
+                              -> Option<Cached<StaticResponse>>
+    where G: AccessGuard
+{
+    let store = store.get();
+    let load = |name: &str| {
+        store.load(name).and_then(|sf| {
+            let allowed = match sf.condition {
+                Some(ref c) => guard.allows(c.as_str()),
+                None => true,
+            };
+            if allowed { Some(sf) } else { None }
+        })
+    };
+
+    let name = match normalize(&path) {
+        Some(n) => n,
+        None => return None,
+    };
+
+    let index = options.index.as_ref().map(|i| if name.is_empty() {
+        i.clone()
+    } else {
+        format!("{}/{}", name, i)
+    });
+
+    let found = load(name.as_str()).or_else(|| index.and_then(|i| load(i.as_str())));
+    let (mut sf, fell_back) = match found {
+        Some(sf) => (sf, false),
+        None => {
+            match options.fallback {
+                Some(ref f) if f.matches(name.as_str()) => {
+                    match load(f.file.as_str()) {
+                        Some(sf) => (sf, true),
+                        None => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+    };
+
+    let cache_control = match (sf.condition.is_some(), fell_back) {
+        (true, true) => Some("private, no-cache"),
+        (true, false) => Some("private"),
+        (false, true) => Some("no-cache"),
+        (false, false) => None,
+    };
+    if let Some(cc) = cache_control {
+        sf.headers.retain(|&(ref k, _)| k != "Cache-Control");
+        sf.headers.push((String::from("Cache-Control"), String::from(cc)));
+    }
+
+    if let Some(IfNoneMatch(ref i)) = inm {
+        if &sf.etag == i {
+            return Some(Cached::Cached);
+        }
+    }
 
-        if let Some(IfNoneMatch(ref i)) = inm {
-            if &sf.etag == i {
-                return Cached::Cached;
+    Some(Cached::Uncached(StaticResponse(sf)))
+}
+
+/// Turn a request path into the `/`-separated name files are stored under.
+fn normalize(path: &Path) -> Option<String> {
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(s) => {
+                match s.to_str() {
+                    Some(seg) if is_plain_segment(seg) => segments.push(seg),
+                    _ => return None,
+                }
             }
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
         }
+    }
+    Some(segments.join("/"))
+}
+
+/// Rocket has already percent-decoded the path, so a `%` means it was encoded twice.
+fn is_plain_segment(seg: &str) -> bool {
+    !seg.chars().any(|c| c == '\\' || c == '%' || c.is_control())
+}
 
-        Cached::Uncached(StaticResponse(sf))
-    })
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+    use std::path::Path;
+
+    #[test]
+    fn accepts_nested_paths() {
+        assert_eq!(normalize(Path::new("css/fonts/a.woff")),
+                   Some(String::from("css/fonts/a.woff")));
+        assert_eq!(normalize(Path::new("/css/./all.css")), Some(String::from("css/all.css")));
+        assert_eq!(normalize(Path::new("")), Some(String::new()));
+    }
+
+    #[test]
+    fn rejects_parent_directories() {
+        assert_eq!(normalize(Path::new("../secret")), None);
+        assert_eq!(normalize(Path::new("css/../../secret")), None);
+        assert_eq!(normalize(Path::new("css/..")), None);
+    }
+
+    #[test]
+    fn rejects_backslashes() {
+        assert_eq!(normalize(Path::new("css\\..\\secret")), None);
+    }
+
+    #[test]
+    fn rejects_percent_signs() {
+        assert_eq!(normalize(Path::new("css/%2e%2e/secret")), None);
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert_eq!(normalize(Path::new("css/all.css\0")), None);
+        assert_eq!(normalize(Path::new("css/a\nb.css")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_non_utf8_segments() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        assert_eq!(normalize(Path::new(OsStr::from_bytes(b"css/\xff.css"))), None);
+    }
 }